@@ -0,0 +1,66 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use humantime_serde::re::humantime;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+/// Prometheus exporter for 1-Wire temperature sensors.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to the TOML configuration file.
+    #[arg(short, long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Defaults to `serve` when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Poll the sensors and serve the `/metrics` endpoint.
+    Serve(ServeArgs),
+    /// Discover sensors and print their ID, family and current reading.
+    List(W1Args),
+    /// Read every polled sensor once and print the results.
+    Read(ReadArgs),
+    /// Validate the configuration file and print a summary.
+    CheckConfig,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct W1Args {
+    /// Directory containing the w1 slave devices.
+    #[arg(long, value_name = "PATH")]
+    pub devices_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ServeArgs {
+    /// Address the `/metrics` endpoint listens on.
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+
+    /// Time between two polls of the sensors, e.g. `30s` or `2m`.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub interval: Option<Duration>,
+
+    #[command(flatten)]
+    pub w1: W1Args,
+}
+
+#[derive(Debug, Args)]
+pub struct ReadArgs {
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+
+    #[command(flatten)]
+    pub w1: W1Args,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
@@ -0,0 +1,209 @@
+use crate::{
+    cli::Format,
+    config::Config,
+    source::{self, Sensor, SensorSource},
+    w1,
+};
+use serde::Serialize;
+use std::{error::Error, sync::Arc};
+
+#[derive(Debug, Serialize)]
+struct Reading {
+    sensor: String,
+    name: String,
+    family: String,
+    chip: String,
+    bus: String,
+    source: String,
+    temperature_celsius: Option<f64>,
+    error: Option<String>,
+}
+
+impl Reading {
+    fn value(&self) -> String {
+        match (self.temperature_celsius, &self.error) {
+            (Some(temp), _) => format!("{:.3}", temp),
+            (None, Some(e)) => format!("error: {}", e),
+            (None, None) => "-".to_string(),
+        }
+    }
+}
+
+fn read_sensor(config: &Config, source: &dyn SensorSource, sensor: &Sensor) -> Reading {
+    // A one-shot read has no history, so an 85°C reading is always rejected.
+    let result = source
+        .read(sensor, &mut || {})
+        .and_then(|measurement| source.check(measurement, None));
+    let (temperature_celsius, error) = match result {
+        Ok(measurement) => (
+            Some(measurement.celsius + config.sensor(&sensor.id).offset),
+            None,
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    Reading {
+        sensor: sensor.id.clone(),
+        name: config
+            .sensor(&sensor.id)
+            .name
+            .unwrap_or_else(|| sensor.id.clone()),
+        family: sensor.family.clone(),
+        chip: sensor.chip.clone(),
+        bus: sensor.bus_name().to_string(),
+        source: sensor.source.to_string(),
+        temperature_celsius,
+        error,
+    }
+}
+
+/// Reads the sensors of every enabled source once. Sources that cannot be
+/// listed are reported and skipped, unless none of them could be listed.
+fn read_all(config: &Arc<Config>) -> Result<Vec<Reading>, Box<dyn Error>> {
+    let sources = source::from_config(config);
+    let mut readings = Vec::new();
+    let mut failed = 0;
+    for source in &sources {
+        match source.discover() {
+            Ok(sensors) => {
+                source.prepare();
+                readings.extend(
+                    sensors
+                        .iter()
+                        .map(|sensor| read_sensor(config, source.as_ref(), sensor)),
+                );
+            }
+            Err(e) => {
+                eprintln!("Failed to discover {} sensors: {}", source.name(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 && failed == sources.len() {
+        return Err("No source could be listed".into());
+    }
+    Ok(readings)
+}
+
+/// Prints every sensor of the enabled sources, including w1 slaves that are
+/// not polled. Only polled sensors are read.
+pub fn list(config: &Arc<Config>) -> Result<(), Box<dyn Error>> {
+    let readings = read_all(config)?;
+    let unpolled: Vec<w1::Slave> = if config.w1.enabled {
+        w1::discover(&config.w1.devices_path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|slave| !config.is_polled(slave))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if readings.is_empty() && unpolled.is_empty() {
+        println!("No sensors found");
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<8} {:<10} {:<16} {:<9} READING",
+        "ID", "FAMILY", "CHIP", "BUS", "SOURCE"
+    );
+    for r in &readings {
+        println!(
+            "{:<20} {:<8} {:<10} {:<16} {:<9} {}",
+            r.sensor,
+            r.family,
+            r.chip,
+            r.bus,
+            r.source,
+            r.value()
+        );
+    }
+    for slave in &unpolled {
+        println!(
+            "{:<20} {:<8} {:<10} {:<16} {:<9} not polled",
+            slave.id,
+            slave.family_code(),
+            slave.family().map_or("unknown", |family| family.chip),
+            slave.bus_name(),
+            "w1"
+        );
+    }
+    Ok(())
+}
+
+/// Reads every polled sensor once and prints the results in `format`.
+pub fn read(config: &Arc<Config>, format: Format) -> Result<(), Box<dyn Error>> {
+    let readings = read_all(config)?;
+
+    match format {
+        Format::Table => {
+            println!(
+                "{:<20} {:<20} {:<8} {:<10} {:<16} {:<9} TEMPERATURE",
+                "SENSOR", "NAME", "FAMILY", "CHIP", "BUS", "SOURCE"
+            );
+            for r in &readings {
+                println!(
+                    "{:<20} {:<20} {:<8} {:<10} {:<16} {:<9} {}",
+                    r.sensor,
+                    r.name,
+                    r.family,
+                    r.chip,
+                    r.bus,
+                    r.source,
+                    r.value()
+                );
+            }
+        }
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(&readings)?);
+        }
+        Format::Csv => {
+            println!("sensor,name,family,chip,bus,source,temperature_celsius,error");
+            for r in &readings {
+                println!(
+                    "{},{},{},{},{},{},{},{}",
+                    r.sensor,
+                    csv_field(&r.name),
+                    r.family,
+                    csv_field(&r.chip),
+                    r.bus,
+                    r.source,
+                    r.temperature_celsius
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                    csv_field(r.error.as_deref().unwrap_or_default()),
+                );
+            }
+        }
+    }
+
+    if readings.iter().all(|r| r.error.is_some()) && !readings.is_empty() {
+        return Err("No sensor could be read".into());
+    }
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Prints a summary of the already loaded and validated configuration.
+pub fn check_config(config: &Config) -> Result<(), Box<dyn Error>> {
+    println!("Configuration OK");
+    println!("  listen:       {}", config.server.listen);
+    println!(
+        "  interval:     {}",
+        humantime_serde::re::humantime::format_duration(config.interval)
+    );
+    println!("  devices_path: {}", config.w1.devices_path.display());
+    println!(
+        "  grace_period: {}",
+        humantime_serde::re::humantime::format_duration(config.grace_period)
+    );
+    println!("  prefixes:     {}", config.w1.prefixes.join(", "));
+    println!("  sensors:      {} configured", config.sensors.len());
+    Ok(())
+}
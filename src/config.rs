@@ -0,0 +1,814 @@
+use crate::w1;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fs,
+    net::SocketAddr,
+    path::Path,
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Labels every sensor series already carries; they cannot be set from the
+/// `labels` table of a sensor. `le` is the bucket label of the read duration
+/// histogram, which the client library refuses as a constant label.
+const RESERVED_LABELS: &[&str] = &[
+    "sensor", "family", "chip", "bus", "name", "source", "reason", "kind", "le",
+];
+
+/// Service configuration, loaded from a TOML file.
+///
+/// Every field has a default matching the behaviour of the original hardcoded
+/// service, so an empty file (or no file at all) is a valid configuration.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub w1: W1Config,
+    pub supervisor: SupervisorConfig,
+    pub scrape: ScrapeConfig,
+    /// owserver instances to read sensors from, as `[[owserver]]` tables.
+    pub owserver: Vec<OwserverConfig>,
+    pub hwmon: HwmonConfig,
+    pub thermal: ThermalConfig,
+    pub iio: IioConfig,
+    /// Time between two polls of the sensors. Polls are aligned to
+    /// multiples of the interval on the wall clock, so a 60s interval polls
+    /// at the start of every minute.
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// How long a sensor may be missing from the bus, or fail to read, before
+    /// its temperature stops being exported. Sensors that are not listed
+    /// under `sensors` are forgotten entirely once missing for this long.
+    #[serde(with = "humantime_serde")]
+    pub grace_period: Duration,
+    /// Device settings shared by all sensors of a family, keyed by family
+    /// code (e.g. `28`). Settings of a sensor take precedence.
+    pub families: HashMap<String, DeviceSettings>,
+    /// Per-sensor settings, keyed by sensor ID (e.g. `28-0316a2791aff` or
+    /// `thermal_zone0`).
+    pub sensors: HashMap<String, SensorConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the `/metrics` endpoint listens on.
+    pub listen: SocketAddr,
+    /// Also answer owserver protocol requests on this address (usually
+    /// port 4304), so OWFS clients can read the sensors without touching the
+    /// bus. Disabled when not set.
+    pub owserver_listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct W1Config {
+    /// Read sensors on the kernel's w1 bus masters.
+    pub enabled: bool,
+    /// Directory containing the w1 slave devices.
+    pub devices_path: PathBuf,
+    /// Only slaves whose ID starts with one of these prefixes are polled.
+    /// Defaults to every supported temperature family.
+    pub prefixes: Vec<String>,
+    /// How many times a reading that failed the CRC check is retried within
+    /// the same poll.
+    pub crc_retries: u32,
+    /// Maximum number of sensors read at the same time. Reads abandoned
+    /// after `read_timeout` count until they actually return.
+    pub max_concurrent_reads: usize,
+    /// A read taking longer than this is abandoned and counted as a timeout.
+    #[serde(with = "humantime_serde")]
+    pub read_timeout: Duration,
+    /// Start the conversion on every sensor of a bus at once through the bus
+    /// master's `therm_bulk_read` attribute, then collect the results. Buses
+    /// without the attribute are read one sensor at a time.
+    pub bulk_read: bool,
+    /// How often to run a hardware alarm search on every bus. Disabled when
+    /// not set. Needs the w1 netlink connector and root privileges.
+    #[serde(with = "humantime_serde")]
+    pub alarm_search_interval: Option<Duration>,
+    /// How often the power mode (`ext_power`) of every sensor is checked.
+    /// New sensors are checked when they are discovered. Ignored in scrape
+    /// mode, which does not export the power mode.
+    #[serde(with = "humantime_serde")]
+    pub power_check_interval: Duration,
+    /// Make parasite-powered sensors read reliably: disable conversion
+    /// polling for them and wait the full datasheet conversion time unless
+    /// `conv_time` is configured. The driver's strong pull-up is a module
+    /// option that cannot be changed at runtime; it is on by default.
+    pub parasite_conversion: bool,
+    /// Listen for the kernel's uevents and pick up sensors as soon as they
+    /// are attached or removed, instead of listing the devices directory
+    /// every poll.
+    pub hotplug: bool,
+    /// With `hotplug`, how often the devices directory is still listed in
+    /// full to catch changes the kernel did not report.
+    #[serde(with = "humantime_serde")]
+    pub resync_interval: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    /// Try to recover buses on which every sensor keeps failing.
+    pub enabled: bool,
+    /// Number of polls at the global `interval` in a row in which every
+    /// sensor of a bus must fail or be missing from the bus before a
+    /// recovery action is taken. Polls
+    /// of sensors with their own interval or triggered by hotplug events do
+    /// not count.
+    pub failure_threshold: u32,
+    /// Minimum time between two recovery actions on the same bus.
+    #[serde(with = "humantime_serde")]
+    pub min_interval: Duration,
+    /// Command run when a rescan did not bring the bus back. `{bus}` in any
+    /// argument is replaced by the bus master name, which is also passed in
+    /// the `W1_BUS` environment variable.
+    pub recovery_command: Option<Vec<String>>,
+    /// The recovery command is killed after this long.
+    #[serde(with = "humantime_serde")]
+    pub command_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SensorConfig {
+    /// Set to `false` to ignore the sensor entirely.
+    pub enabled: bool,
+    /// Calibration offset in degrees Celsius added to every reading.
+    pub offset: f64,
+    /// Friendly name, exported as the `name` label. Defaults to the sensor ID.
+    pub name: Option<String>,
+    /// Extra labels attached to every series of the sensor, e.g.
+    /// `{ room = "kitchen", floor = "1" }`.
+    pub labels: BTreeMap<String, String>,
+    /// Overrides `w1.read_timeout` for this sensor.
+    #[serde(with = "humantime_serde")]
+    pub read_timeout: Option<Duration>,
+    /// Overrides `interval` for this sensor. Sensors with the same interval
+    /// are read together.
+    #[serde(with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    /// Conversion resolution in bits (9–12). Overrides the family setting.
+    pub resolution: Option<u8>,
+    /// Conversion time written to the driver. Overrides the family setting.
+    #[serde(with = "humantime_serde")]
+    pub conv_time: Option<Duration>,
+    /// Overrides the family's `persist` setting.
+    pub persist: Option<bool>,
+    /// Hardware alarm thresholds (TL, TH) in whole degrees Celsius, written to
+    /// the chip and reported by the alarm search.
+    pub alarm_low: Option<i8>,
+    pub alarm_high: Option<i8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrapeConfig {
+    /// Read the sensors when `/metrics` is requested instead of polling
+    /// them in the background.
+    pub enabled: bool,
+    /// Readings are reused for scrapes within this long of the read.
+    #[serde(with = "humantime_serde")]
+    pub cache_ttl: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OwserverConfig {
+    /// `host:port` of the owserver; the port defaults to 4304.
+    pub address: String,
+    /// Family codes of the devices to read, e.g. `28`. Defaults to every
+    /// supported temperature family.
+    pub families: Vec<String>,
+    /// Timeout for connecting to the server and for each answer.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+/// Temperatures of the kernel's hardware monitoring drivers, such as the
+/// CPU or an NVMe drive.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HwmonConfig {
+    pub enabled: bool,
+    /// The hwmon class directory.
+    pub path: PathBuf,
+}
+
+/// Temperatures of the kernel's thermal zones, such as `cpu-thermal` on a
+/// Raspberry Pi.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThermalConfig {
+    pub enabled: bool,
+    /// The thermal class directory.
+    pub path: PathBuf,
+}
+
+/// Environmental sensors such as the BME280 or DHT22, read through the
+/// kernel's Industrial I/O drivers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IioConfig {
+    pub enabled: bool,
+    /// The IIO devices directory.
+    pub devices_path: PathBuf,
+}
+
+/// Settings applied to the device itself when a sensor is discovered.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceSettings {
+    /// Conversion resolution in bits (9–12). The MAX31826, which shares
+    /// family code `3b` with the DS1825, is fixed at 12 bits and ignores it.
+    pub resolution: Option<u8>,
+    /// Conversion time written to the driver's `conv_time` attribute.
+    #[serde(with = "humantime_serde")]
+    pub conv_time: Option<Duration>,
+    /// Save changed settings to the chip's EEPROM so they survive a power
+    /// cycle. The EEPROM is only written when a setting actually changed.
+    pub persist: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig::default(),
+            w1: W1Config::default(),
+            supervisor: SupervisorConfig::default(),
+            scrape: ScrapeConfig::default(),
+            owserver: Vec::new(),
+            hwmon: HwmonConfig::default(),
+            thermal: ThermalConfig::default(),
+            iio: IioConfig::default(),
+            interval: Duration::from_secs(60),
+            grace_period: Duration::from_secs(300),
+            families: HashMap::new(),
+            sensors: HashMap::new(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 9091)),
+            owserver_listen: None,
+        }
+    }
+}
+
+impl Default for HwmonConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/sys/class/hwmon"),
+        }
+    }
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/sys/class/thermal"),
+        }
+    }
+}
+
+impl Default for IioConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            devices_path: PathBuf::from("/sys/bus/iio/devices"),
+        }
+    }
+}
+
+impl Default for W1Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            devices_path: PathBuf::from("/sys/bus/w1/devices"),
+            prefixes: w1::FAMILIES
+                .iter()
+                .map(|family| format!("{}-", family.code))
+                .collect(),
+            crc_retries: 2,
+            max_concurrent_reads: 4,
+            read_timeout: Duration::from_secs(5),
+            bulk_read: false,
+            alarm_search_interval: None,
+            power_check_interval: Duration::from_secs(600),
+            parasite_conversion: false,
+            hotplug: false,
+            resync_interval: Duration::from_secs(600),
+        }
+    }
+}
+
+impl Default for ScrapeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cache_ttl: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Default for OwserverConfig {
+    fn default() -> Self {
+        Self {
+            address: "localhost:4304".to_string(),
+            families: w1::FAMILIES
+                .iter()
+                .map(|family| family.code.to_string())
+                .collect(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            failure_threshold: 3,
+            min_interval: Duration::from_secs(600),
+            recovery_command: None,
+            command_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            offset: 0.0,
+            name: None,
+            labels: BTreeMap::new(),
+            read_timeout: None,
+            interval: None,
+            resolution: None,
+            conv_time: None,
+            persist: None,
+            alarm_low: None,
+            alarm_high: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads and validates the configuration file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads the configuration from `path` if given, otherwise uses the defaults.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        match path {
+            Some(path) => Self::load(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.interval.is_zero() {
+            return Err("interval must be greater than zero".into());
+        }
+        if self.grace_period < self.interval {
+            return Err("grace_period must not be shorter than interval".into());
+        }
+        if self.w1.read_timeout.is_zero() {
+            return Err("w1.read_timeout must be greater than zero".into());
+        }
+        if self.w1.alarm_search_interval.is_some_and(|i| i.is_zero()) {
+            return Err("w1.alarm_search_interval must be greater than zero".into());
+        }
+        if self.w1.power_check_interval.is_zero() {
+            return Err("w1.power_check_interval must be greater than zero".into());
+        }
+        if self.w1.resync_interval.is_zero() {
+            return Err("w1.resync_interval must be greater than zero".into());
+        }
+        if self.w1.max_concurrent_reads == 0 {
+            return Err("w1.max_concurrent_reads must be greater than zero".into());
+        }
+        if self.w1.prefixes.is_empty() {
+            return Err("w1.prefixes must not be empty".into());
+        }
+        if self.scrape.enabled {
+            if self.scrape.cache_ttl.is_zero() {
+                return Err("scrape.cache_ttl must be greater than zero".into());
+            }
+            if self.supervisor.enabled {
+                return Err("supervisor.enabled cannot be combined with scrape.enabled".into());
+            }
+            if self.server.owserver_listen.is_some() {
+                return Err("server.owserver_listen cannot be combined with scrape.enabled".into());
+            }
+            if self.w1.alarm_search_interval.is_some() {
+                return Err(
+                    "w1.alarm_search_interval cannot be combined with scrape.enabled".into(),
+                );
+            }
+            // Every sensor is read on every refresh and the directory is
+            // listed each time, so schedules and hotplug events have no use.
+            if self.w1.hotplug {
+                return Err("w1.hotplug cannot be combined with scrape.enabled".into());
+            }
+            // Device settings are written when the poller discovers a
+            // sensor, which does not happen in scrape mode.
+            if self.w1.parasite_conversion {
+                return Err("w1.parasite_conversion cannot be combined with scrape.enabled".into());
+            }
+            if let Some(code) = self.families.keys().next() {
+                return Err(format!(
+                    "families.{}: device settings cannot be combined with scrape.enabled",
+                    code
+                )
+                .into());
+            }
+            for (id, sensor) in &self.sensors {
+                if sensor.interval.is_some() {
+                    return Err(format!(
+                        "sensors.{}: interval cannot be combined with scrape.enabled",
+                        id
+                    )
+                    .into());
+                }
+                if sensor.resolution.is_some()
+                    || sensor.conv_time.is_some()
+                    || sensor.persist.is_some()
+                    || sensor.alarm_low.is_some()
+                    || sensor.alarm_high.is_some()
+                {
+                    return Err(format!(
+                        "sensors.{}: device settings cannot be combined with scrape.enabled",
+                        id
+                    )
+                    .into());
+                }
+            }
+        }
+        for server in &self.owserver {
+            if server.address.is_empty() {
+                return Err("owserver.address must not be empty".into());
+            }
+            if server.timeout.is_zero() {
+                return Err(format!(
+                    "owserver {}: timeout must be greater than zero",
+                    server.address
+                )
+                .into());
+            }
+            if let Some(code) = server
+                .families
+                .iter()
+                .find(|code| w1::family::lookup(code).is_none())
+            {
+                return Err(format!(
+                    "owserver {}: unknown family code {:?}",
+                    server.address, code
+                )
+                .into());
+            }
+        }
+        if self.supervisor.failure_threshold == 0 {
+            return Err("supervisor.failure_threshold must be greater than zero".into());
+        }
+        if self
+            .supervisor
+            .recovery_command
+            .as_ref()
+            .is_some_and(|command| command.is_empty())
+        {
+            return Err("supervisor.recovery_command must not be empty".into());
+        }
+        for (code, settings) in &self.families {
+            let family = w1::family::lookup(code)
+                .ok_or_else(|| format!("families.{}: unknown family code", code))?;
+            validate_resolution(&format!("families.{}", code), family, settings.resolution)?;
+        }
+        for (id, sensor) in &self.sensors {
+            match (sensor.alarm_low, sensor.alarm_high) {
+                (None, None) => {}
+                (Some(low), Some(high)) if low <= high && low >= -55 && high <= 125 => {}
+                (Some(_), Some(_)) => {
+                    return Err(format!(
+                        "sensors.{}: alarm thresholds must satisfy -55 <= alarm_low <= alarm_high <= 125",
+                        id
+                    )
+                    .into())
+                }
+                _ => {
+                    return Err(format!(
+                        "sensors.{}: alarm_low and alarm_high must be set together",
+                        id
+                    )
+                    .into())
+                }
+            }
+            if let Some(family) = w1::family::lookup(id.split('-').next().unwrap_or_default()) {
+                validate_resolution(&format!("sensors.{}", id), family, sensor.resolution)?;
+            }
+            if !sensor.offset.is_finite() {
+                return Err(format!("sensors.{}: offset must be a finite number", id).into());
+            }
+            if sensor.read_timeout.is_some_and(|timeout| timeout.is_zero()) {
+                return Err(
+                    format!("sensors.{}: read_timeout must be greater than zero", id).into(),
+                );
+            }
+            if let Some(interval) = sensor.interval {
+                if interval.is_zero() {
+                    return Err(
+                        format!("sensors.{}: interval must be greater than zero", id).into(),
+                    );
+                }
+                if self.grace_period < interval {
+                    return Err(format!(
+                        "sensors.{}: grace_period must not be shorter than interval",
+                        id
+                    )
+                    .into());
+                }
+            }
+            for label in sensor.labels.keys() {
+                if !is_valid_label_name(label) || RESERVED_LABELS.contains(&label.as_str()) {
+                    return Err(format!("sensors.{}: invalid label name {:?}", id, label).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the slave should be polled: it belongs to a supported
+    /// family, its prefix is selected and it is not disabled.
+    pub fn is_polled(&self, slave: &w1::Slave) -> bool {
+        slave.family().is_some()
+            && self
+                .w1
+                .prefixes
+                .iter()
+                .any(|p| slave.id.starts_with(p.as_str()))
+            && self.sensor(&slave.id).enabled
+    }
+
+    /// Settings for the given sensor, falling back to the defaults when the
+    /// sensor has no entry of its own.
+    pub fn sensor(&self, id: &str) -> SensorConfig {
+        self.sensors.get(id).cloned().unwrap_or_default()
+    }
+
+    /// Settings to apply to a sensor's device, merged from its own entry and
+    /// the entry of its family.
+    pub fn target_settings(&self, slave: &w1::Slave) -> w1::TargetSettings {
+        let sensor = self.sensor(&slave.id);
+        let family = self
+            .families
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(slave.family_code()))
+            .map(|(_, settings)| settings.clone())
+            .unwrap_or_default();
+        w1::TargetSettings {
+            resolution: sensor.resolution.or(family.resolution),
+            conv_time: sensor.conv_time.or(family.conv_time),
+            alarms: sensor.alarm_low.zip(sensor.alarm_high),
+            persist: sensor.persist.or(family.persist).unwrap_or(false),
+            parasite_conversion: self.w1.parasite_conversion,
+        }
+    }
+
+    /// How long a read of the given sensor may take before it is abandoned.
+    pub fn read_timeout(&self, id: &str) -> Duration {
+        self.sensor(id).read_timeout.unwrap_or(self.w1.read_timeout)
+    }
+
+    /// Time between two reads of the given sensor.
+    pub fn poll_interval(&self, id: &str) -> Duration {
+        self.sensor(id).interval.unwrap_or(self.interval)
+    }
+
+    /// Every poll interval in use: the global one and the per-sensor ones.
+    pub fn poll_intervals(&self) -> BTreeSet<Duration> {
+        self.sensors
+            .values()
+            .filter_map(|sensor| sensor.interval)
+            .chain([self.interval])
+            .collect()
+    }
+
+    /// The `name` and extra labels of a sensor. Prometheus requires every
+    /// series of a metric to have the same label names, so labels that are
+    /// only configured for other sensors are included with an empty value.
+    pub fn sensor_labels(&self, id: &str) -> BTreeMap<String, String> {
+        let sensor = self.sensor(id);
+        let mut labels: BTreeMap<String, String> = self
+            .label_names()
+            .into_iter()
+            .map(|label| {
+                let value = sensor.labels.get(&label).cloned().unwrap_or_default();
+                (label, value)
+            })
+            .collect();
+        labels.insert(
+            "name".to_string(),
+            sensor.name.unwrap_or_else(|| id.to_string()),
+        );
+        labels
+    }
+
+    /// Names of all extra labels configured on any sensor.
+    fn label_names(&self) -> BTreeSet<String> {
+        self.sensors
+            .values()
+            .flat_map(|sensor| sensor.labels.keys().cloned())
+            .collect()
+    }
+}
+
+fn validate_resolution(
+    key: &str,
+    family: &w1::Family,
+    resolution: Option<u8>,
+) -> Result<(), Box<dyn Error>> {
+    match resolution {
+        Some(_) if !family.configurable_resolution => {
+            Err(format!("{}: the {} has a fixed resolution", key, family.chip).into())
+        }
+        Some(bits) if !(9..=12).contains(&bits) => {
+            Err(format!("{}: resolution must be between 9 and 12 bits", key).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Config, Box<dyn Error>> {
+        let config: Config = toml::from_str(toml)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn rejects_reserved_labels() {
+        for label in RESERVED_LABELS {
+            let toml = format!("[sensors.28-a]\nlabels = {{ {} = \"x\" }}", label);
+            let e = parse(&toml).unwrap_err().to_string();
+            assert!(e.contains("invalid label name"), "{}: {}", label, e);
+        }
+        parse("[sensors.28-a]\nlabels = { room = \"kitchen\" }").unwrap();
+    }
+
+    #[test]
+    fn rejects_poller_settings_in_scrape_mode() {
+        let scrape = "[scrape]\nenabled = true\n";
+        parse(scrape).unwrap();
+        for (setting, key) in [
+            ("[w1]\nhotplug = true", "w1.hotplug"),
+            ("[w1]\nparasite_conversion = true", "w1.parasite_conversion"),
+            ("[supervisor]\nenabled = true", "supervisor.enabled"),
+            ("[families.28]\nresolution = 10", "families.28"),
+            (
+                "[sensors.28-a]\ninterval = \"10s\"",
+                "sensors.28-a: interval",
+            ),
+            ("[sensors.28-a]\npersist = true", "sensors.28-a"),
+        ] {
+            let e = parse(&format!("{}{}", scrape, setting)).unwrap_err();
+            assert!(e.to_string().contains(key), "{}", e);
+            parse(setting).unwrap();
+        }
+    }
+
+    #[test]
+    fn accepts_an_empty_file() {
+        let config = parse("").unwrap();
+        assert_eq!(config.interval, Duration::from_secs(60));
+        assert_eq!(config.grace_period, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn rejects_invalid_intervals() {
+        let e = parse("interval = \"0s\"").unwrap_err();
+        assert_eq!(e.to_string(), "interval must be greater than zero");
+        let e = parse("interval = \"10m\"\ngrace_period = \"5m\"").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "grace_period must not be shorter than interval"
+        );
+        let config = parse("interval = \"10m\"\ngrace_period = \"10m\"").unwrap();
+        assert_eq!(config.interval, Duration::from_secs(600));
+        assert!(parse("interval = \"soon\"").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(parse("intervall = \"10s\"").is_err());
+        assert!(parse("[sensors.28-a]\nofset = 0.5").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_label_names() {
+        for label in ["1st", "room-name", "__meta", "räum", ""] {
+            let toml = format!("[sensors.28-a]\nlabels = {{ \"{}\" = \"x\" }}", label);
+            let e = parse(&toml).unwrap_err().to_string();
+            assert!(e.contains("invalid label name"), "{}: {}", label, e);
+        }
+        for label in ["room", "_floor", "rack2"] {
+            assert!(is_valid_label_name(label), "{}", label);
+        }
+    }
+
+    #[test]
+    fn fills_in_missing_labels() {
+        let config = parse(
+            "[sensors.28-a]\nname = \"freezer\"\nlabels = { room = \"garage\" }\n\
+             [sensors.28-b]\nlabels = { floor = \"1\" }",
+        )
+        .unwrap();
+        let labels = |id| {
+            config
+                .sensor_labels(id)
+                .into_iter()
+                .collect::<Vec<(String, String)>>()
+        };
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            labels("28-a"),
+            pairs(&[("floor", ""), ("name", "freezer"), ("room", "garage")])
+        );
+        assert_eq!(
+            labels("28-b"),
+            pairs(&[("floor", "1"), ("name", "28-b"), ("room", "")])
+        );
+        assert_eq!(
+            labels("28-c"),
+            pairs(&[("floor", ""), ("name", "28-c"), ("room", "")])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_alarm_thresholds() {
+        let alarms = |low: &str, high: &str| {
+            let mut toml = "[sensors.28-a]\n".to_string();
+            if !low.is_empty() {
+                toml += &format!("alarm_low = {}\n", low);
+            }
+            if !high.is_empty() {
+                toml += &format!("alarm_high = {}\n", high);
+            }
+            parse(&toml).map(|_| ()).map_err(|e| e.to_string())
+        };
+        assert_eq!(alarms("-10", "-5"), Ok(()));
+        assert_eq!(alarms("-55", "125"), Ok(()));
+        assert_eq!(alarms("4", "4"), Ok(()));
+        for (low, high) in [("5", "4"), ("-56", "0"), ("0", "126")] {
+            let e = alarms(low, high).unwrap_err();
+            assert!(e.contains("-55 <= alarm_low <= alarm_high <= 125"), "{}", e);
+        }
+        for (low, high) in [("5", ""), ("", "5")] {
+            let e = alarms(low, high).unwrap_err();
+            assert!(e.contains("must be set together"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_resolutions() {
+        let e = parse("[families.10]\nresolution = 10").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "families.10: the DS18S20 has a fixed resolution"
+        );
+        let e = parse("[families.28]\nresolution = 13").unwrap_err();
+        assert!(e.to_string().contains("between 9 and 12 bits"), "{}", e);
+        let e = parse("[families.99]\nresolution = 12").unwrap_err();
+        assert!(e.to_string().contains("unknown family code"), "{}", e);
+        parse("[families.28]\nresolution = 9").unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+//! Hotplug events from the kernel's uevent netlink socket. sysfs does not
+//! report devices being added or removed to inotify, so the uevents the
+//! kernel broadcasts for udev are the only reliable notification.
+
+use crate::source::Sensor;
+use std::{io, mem, thread, time::Duration};
+use tokio::sync::mpsc;
+
+const NETLINK_KOBJECT_UEVENT: libc::c_int = 15;
+/// The multicast group the kernel sends uevents to, as opposed to the one
+/// udev re-broadcasts them on.
+const KERNEL_GROUP: u32 = 1;
+
+/// A change of the devices of a subsystem.
+#[derive(Debug)]
+pub enum Event {
+    /// A sensor was attached.
+    Added(Sensor),
+    /// The device with this name was removed. It is not necessarily a
+    /// sensor the source reported.
+    Removed(String),
+    /// The kernel dropped events; the devices have to be listed again.
+    Overflow,
+}
+
+/// Hotplug events of a source.
+pub struct Watcher {
+    /// Closed when the watcher stops.
+    pub events: mpsc::Receiver<Event>,
+    /// How often the source should still be listed in full, to catch
+    /// changes that were not reported.
+    pub resync_interval: Duration,
+}
+
+/// A uevent netlink socket subscribed to the kernel's broadcasts.
+struct Socket(libc::c_int);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+impl Socket {
+    fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = Socket(fd);
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = KERNEL_GROUP;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret =
+            unsafe { libc::recv(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+}
+
+/// What a uevent reports about a device.
+#[derive(Debug, PartialEq, Eq)]
+enum Change<'a> {
+    Add(&'a str),
+    Remove(&'a str),
+}
+
+/// Parses a kernel uevent such as `add@/devices/w1_bus_master1/28-...`
+/// followed by NUL-separated `KEY=value` pairs. Returns the name of the
+/// device if it was added to or removed from `subsystem`.
+fn parse_uevent<'a>(buf: &'a [u8], subsystem: &str) -> Option<Change<'a>> {
+    let mut fields = buf
+        .split(|&b| b == 0)
+        .filter_map(|field| std::str::from_utf8(field).ok());
+    // The summary line; everything needed is repeated in the fields.
+    fields.next()?.split_once('@')?;
+
+    let (mut action, mut devpath, mut matches) = (None, None, false);
+    for field in fields {
+        match field.split_once('=') {
+            Some(("ACTION", value)) => action = Some(value),
+            Some(("DEVPATH", value)) => devpath = Some(value),
+            Some(("SUBSYSTEM", value)) => matches = value == subsystem,
+            _ => {}
+        }
+    }
+    if !matches {
+        return None;
+    }
+    let name = devpath?.rsplit('/').next()?;
+    match action? {
+        "add" => Some(Change::Add(name)),
+        "remove" => Some(Change::Remove(name)),
+        _ => None,
+    }
+}
+
+/// Watches for devices being added to and removed from `subsystem`.
+/// `sensor` turns the name of a new device into a sensor, or returns `None`
+/// to ignore it. The watcher runs on a thread of its own and stops when the
+/// receiver is dropped or reading events fails, which closes the channel.
+pub fn watch(
+    subsystem: &'static str,
+    sensor: impl Fn(&str) -> Option<Sensor> + Send + 'static,
+) -> io::Result<mpsc::Receiver<Event>> {
+    let socket = Socket::open()?;
+
+    let (tx, rx) = mpsc::channel(64);
+    thread::Builder::new()
+        .name("hotplug".into())
+        .spawn(move || {
+            let mut buffer = [0; 8192];
+            loop {
+                let event = match socket.recv(&mut buffer) {
+                    Ok(len) => match parse_uevent(&buffer[..len], subsystem) {
+                        Some(Change::Add(name)) => match sensor(name) {
+                            Some(sensor) => Event::Added(sensor),
+                            None => continue,
+                        },
+                        Some(Change::Remove(name)) => Event::Removed(name.to_string()),
+                        None => continue,
+                    },
+                    // The socket buffer overflowed and uevents were dropped.
+                    Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => Event::Overflow,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        eprintln!("Failed to read {} hotplug events: {}", subsystem, e);
+                        return;
+                    }
+                };
+                if tx.blocking_send(event).is_err() {
+                    return;
+                }
+            }
+        })?;
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uevent(action: &str, devpath: &str, subsystem: &str) -> Vec<u8> {
+        format!(
+            "{action}@{devpath}\0ACTION={action}\0DEVPATH={devpath}\0SUBSYSTEM={subsystem}\0SEQNUM=1\0"
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn parses_added_and_removed_slaves() {
+        let devpath = "/devices/w1_bus_master1/28-0316a2791aff";
+        assert_eq!(
+            parse_uevent(&uevent("add", devpath, "w1"), "w1"),
+            Some(Change::Add("28-0316a2791aff"))
+        );
+        assert_eq!(
+            parse_uevent(&uevent("remove", devpath, "w1"), "w1"),
+            Some(Change::Remove("28-0316a2791aff"))
+        );
+    }
+
+    #[test]
+    fn ignores_other_subsystems_and_actions() {
+        let devpath = "/devices/w1_bus_master1/28-0316a2791aff";
+        assert_eq!(parse_uevent(&uevent("add", devpath, "usb"), "w1"), None);
+        assert_eq!(parse_uevent(&uevent("change", devpath, "w1"), "w1"), None);
+        assert_eq!(parse_uevent(b"libudev\0garbage", "w1"), None);
+        assert_eq!(parse_uevent(b"", "w1"), None);
+    }
+}
@@ -1,105 +1,139 @@
+mod cli;
+mod commands;
+mod config;
+mod hotplug;
+mod metrics;
+mod owserver;
+mod poller;
+mod schedule;
+mod scrape;
+mod source;
+mod supervisor;
+mod w1;
+
 use axum::{extract::State, response::IntoResponse, routing::get, Router};
+use clap::Parser;
+use cli::{Cli, Command, ServeArgs, W1Args};
+use config::Config;
 use parking_lot::RwLock;
-use prometheus::{Encoder, Gauge, Opts, Registry, TextEncoder};
-use std::{
-    collections::HashMap, error::Error, fs, net::SocketAddr, path::Path, sync::Arc, time::Duration,
-};
-use tokio::time;
+use poller::TrackedSensor;
+use prometheus::{Encoder, Registry, TextEncoder};
+use std::{collections::HashMap, error::Error, process::ExitCode, sync::Arc};
 
 #[derive(Clone)]
 struct AppState {
     registry: Arc<Registry>,
-    temperature_gauges: Arc<RwLock<HashMap<String, Gauge>>>,
+    sensors: Arc<RwLock<HashMap<String, TrackedSensor>>>,
 }
 
 async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
     let encoder = TextEncoder::new();
-    let metric_families = state.registry.gather();
+    // The scrape collector reads sensors while gathering, which blocks.
+    let registry = state.registry.clone();
+    let metric_families = tokio::task::spawn_blocking(move || registry.gather())
+        .await
+        .unwrap();
     let mut buffer = Vec::new();
     encoder.encode(&metric_families, &mut buffer).unwrap();
     String::from_utf8(buffer).unwrap()
 }
 
-fn read_temperature(device_path: &Path) -> Result<f64, Box<dyn Error>> {
-    let content = fs::read_to_string(device_path.join("w1_slave"))?;
-    let temp_line = content.lines().nth(1).ok_or("Temperature data not found")?;
-
-    let temp_str = temp_line
-        .split("t=")
-        .nth(1)
-        .ok_or("Temperature value not found")?;
-
-    let temp_raw: i32 = temp_str.parse()?;
-    Ok(temp_raw as f64 / 1000.0)
+fn apply_w1_args(config: &mut Config, args: W1Args) {
+    if let Some(devices_path) = args.devices_path {
+        config.w1.devices_path = devices_path;
+    }
 }
 
-async fn update_temperatures(devices_path: &Path, state: AppState) {
-    loop {
-        match fs::read_dir(devices_path) {
-            Ok(entries) => {
-                let mut gauges = state.temperature_gauges.write();
-                let sensors = entries
-                    .filter_map(Result::ok)
-                    .filter(|entry| entry.file_name().to_string_lossy().starts_with("28-"));
-
-                for sensor in sensors {
-                    let sensor_name = sensor.file_name().to_string_lossy().into_owned();
-                    match read_temperature(&sensor.path()) {
-                        Ok(temp) => {
-                            // Get or create gauge for this sensor
-                            let gauge = gauges.entry(sensor_name.clone()).or_insert_with(|| {
-                                let opts = Opts::new(
-                                    "temperature_celsius",
-                                    "Temperature reading in degrees Celsius",
-                                )
-                                .const_label("sensor", &sensor_name);
-                                let gauge = Gauge::with_opts(opts).unwrap();
-                                state.registry.register(Box::new(gauge.clone())).unwrap();
-                                gauge
-                            });
-
-                            gauge.set(temp);
-                            println!("Temperature for {}: {:.3}°C", sensor_name, temp);
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to read temperature from {}: {}", sensor_name, e)
-                        }
-                    }
-                }
-            }
-            Err(e) => eprintln!("Failed to read devices directory: {}", e),
-        }
-
-        time::sleep(Duration::from_secs(60)).await;
+async fn serve(mut config: Config, args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    if let Some(listen) = args.listen {
+        config.server.listen = listen;
     }
-}
+    if let Some(interval) = args.interval {
+        config.interval = interval;
+    }
+    apply_w1_args(&mut config, args.w1);
+    config.validate()?;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
     println!("Starting temperature monitoring service");
 
     let state = AppState {
         registry: Arc::new(Registry::new()),
-        temperature_gauges: Arc::new(RwLock::new(HashMap::new())),
+        sensors: Arc::new(RwLock::new(HashMap::new())),
     };
 
-    let devices_path = "/sys/bus/w1/devices";
-    let app_state = state.clone();
-    tokio::spawn(async move {
-        update_temperatures(Path::new(devices_path), app_state).await;
-    });
+    let config = Arc::new(config);
+    let listen = config.server.listen;
+    let owserver_listen = config.server.owserver_listen;
+    let sources = source::from_config(&config);
+    if config.scrape.enabled {
+        let collector = scrape::ScrapeCollector::new(config.clone(), sources);
+        state.registry.register(Box::new(collector))?;
+    } else {
+        let app_state = state.clone();
+        let poll_config = config.clone();
+        tokio::spawn(async move {
+            poller::update_temperatures(&poll_config, sources, app_state).await;
+        });
+    }
+
+    if let Some(interval) = config
+        .w1
+        .alarm_search_interval
+        .filter(|_| config.w1.enabled)
+    {
+        let app_state = state.clone();
+        tokio::spawn(async move {
+            poller::search_alarms(&config, interval, app_state).await;
+        });
+    }
+
+    if let Some(owserver_listen) = owserver_listen {
+        let app_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = owserver::serve(owserver_listen, app_state).await {
+                eprintln!("Failed to start owserver on {}: {}", owserver_listen, e);
+            }
+        });
+    }
 
     let app = Router::new()
         .route("/metrics", get(metrics_handler))
         .with_state(state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 9091));
-    println!("Starting server on {}", addr);
+    println!("Starting server on {}", listen);
     axum::serve(
-        tokio::net::TcpListener::bind(addr).await?,
+        tokio::net::TcpListener::bind(listen).await?,
         app.into_make_service(),
     )
     .await?;
 
     Ok(())
 }
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let mut config = Config::load_or_default(cli.config.as_deref())?;
+
+    match cli.command.unwrap_or(Command::Serve(ServeArgs::default())) {
+        Command::Serve(args) => serve(config, args).await,
+        Command::List(args) => {
+            apply_w1_args(&mut config, args);
+            commands::list(&Arc::new(config))
+        }
+        Command::Read(args) => {
+            apply_w1_args(&mut config, args.w1);
+            commands::read(&Arc::new(config), args.format)
+        }
+        Command::CheckConfig => commands::check_config(&config),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
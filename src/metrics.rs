@@ -0,0 +1,321 @@
+use crate::{source::Sensor, w1::BusStats};
+use prometheus::{
+    core::Collector, Gauge, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, SystemTime},
+};
+
+/// Histogram buckets for sensor reads. A 12-bit DS18B20 conversion alone
+/// takes 750ms, CRC retries multiply that.
+const READ_DURATION_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 0.75, 1.0, 1.5, 2.5, 5.0, 10.0];
+
+/// The collectors exported for a single sensor, all carrying its labels.
+pub struct SensorMetrics {
+    labels: HashMap<String, String>,
+    /// Registered on the first successful reading, so a sensor that never
+    /// produced a value does not show up as 0°C.
+    temperature: Option<Gauge>,
+    /// Registered on the first reading that includes it, for devices that
+    /// measure humidity.
+    humidity: Option<Gauge>,
+    /// Registered on the first reading that includes it, for devices that
+    /// measure air pressure.
+    pressure: Option<Gauge>,
+    /// Registered once the resolution of the device is known.
+    resolution: Option<IntGauge>,
+    /// Registered after the first alarm search, for sensors with thresholds.
+    hw_alarm: Option<IntGauge>,
+    /// Registered once the power mode of the device is known.
+    external_power: Option<IntGauge>,
+    present: IntGauge,
+    last_success: Gauge,
+    read_errors: IntCounterVec,
+    read_duration: Histogram,
+    crc_failures: IntCounter,
+    invalid_readings: IntCounterVec,
+}
+
+impl SensorMetrics {
+    /// Registers the collectors of `sensor`. `extra_labels` are added to the
+    /// built-in `sensor`, `family`, `chip`, `bus` and `source` labels.
+    pub fn new(
+        registry: &Registry,
+        sensor: &Sensor,
+        extra_labels: BTreeMap<String, String>,
+    ) -> Self {
+        let mut labels = sensor_labels(sensor);
+        labels.extend(extra_labels);
+
+        let opts = Opts::new(
+            "temperature_sensor_present",
+            "Whether the sensor was found on the bus during the last poll",
+        )
+        .const_labels(labels.clone());
+        let present = IntGauge::with_opts(opts).unwrap();
+        registry.register(Box::new(present.clone())).unwrap();
+
+        let opts = Opts::new(
+            "temperature_sensor_last_success_timestamp_seconds",
+            "Unix time of the last successful reading",
+        )
+        .const_labels(labels.clone());
+        let last_success = Gauge::with_opts(opts).unwrap();
+        registry.register(Box::new(last_success.clone())).unwrap();
+
+        let opts = Opts::new(
+            "temperature_sensor_read_errors_total",
+            "Number of failed readings by kind of error",
+        )
+        .const_labels(labels.clone());
+        let read_errors = IntCounterVec::new(opts, &["kind"]).unwrap();
+        registry.register(Box::new(read_errors.clone())).unwrap();
+
+        let opts = HistogramOpts::new(
+            "temperature_sensor_read_duration_seconds",
+            "Time taken to read the sensor, including retries",
+        )
+        .const_labels(labels.clone())
+        .buckets(READ_DURATION_BUCKETS.to_vec());
+        let read_duration = Histogram::with_opts(opts).unwrap();
+        registry.register(Box::new(read_duration.clone())).unwrap();
+
+        let opts = Opts::new(
+            "temperature_sensor_crc_failures_total",
+            "Number of readings rejected because the CRC check failed",
+        )
+        .const_labels(labels.clone());
+        let crc_failures = IntCounter::with_opts(opts).unwrap();
+        registry.register(Box::new(crc_failures.clone())).unwrap();
+
+        let opts = Opts::new(
+            "temperature_sensor_invalid_readings_total",
+            "Number of readings dropped because they were a sentinel value",
+        )
+        .const_labels(labels.clone());
+        let invalid_readings = IntCounterVec::new(opts, &["reason"]).unwrap();
+        registry
+            .register(Box::new(invalid_readings.clone()))
+            .unwrap();
+
+        Self {
+            labels,
+            temperature: None,
+            humidity: None,
+            pressure: None,
+            resolution: None,
+            hw_alarm: None,
+            external_power: None,
+            present,
+            last_success,
+            read_errors,
+            read_duration,
+            crc_failures,
+            invalid_readings,
+        }
+    }
+
+    /// The last exported temperature, if the sensor was ever read successfully.
+    pub fn temperature(&self) -> Option<f64> {
+        self.temperature.as_ref().map(Gauge::get)
+    }
+
+    pub fn set_temperature(&mut self, registry: &Registry, temp: f64) {
+        let gauge = self.temperature.get_or_insert_with(|| {
+            let opts = Opts::new(
+                "temperature_celsius",
+                "Temperature reading in degrees Celsius",
+            )
+            .const_labels(self.labels.clone());
+            let gauge = Gauge::with_opts(opts).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        });
+        gauge.set(temp);
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_success.set(now.as_secs_f64());
+    }
+
+    pub fn set_humidity(&mut self, registry: &Registry, percent: f64) {
+        let gauge = self.humidity.get_or_insert_with(|| {
+            let opts = Opts::new("humidity_percent", "Relative humidity in percent")
+                .const_labels(self.labels.clone());
+            let gauge = Gauge::with_opts(opts).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        });
+        gauge.set(percent);
+    }
+
+    pub fn set_pressure(&mut self, registry: &Registry, pascals: f64) {
+        let gauge = self.pressure.get_or_insert_with(|| {
+            let opts = Opts::new("pressure_pascals", "Air pressure in pascals")
+                .const_labels(self.labels.clone());
+            let gauge = Gauge::with_opts(opts).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        });
+        gauge.set(pascals);
+    }
+
+    /// Unregisters the temperature, humidity and pressure gauges so stale
+    /// values are no longer exported. They are registered again on the next
+    /// successful reading.
+    pub fn clear_readings(&mut self, registry: &Registry) {
+        let readings = [
+            self.temperature.take(),
+            self.humidity.take(),
+            self.pressure.take(),
+        ];
+        for gauge in readings.into_iter().flatten() {
+            unregister(registry, gauge);
+        }
+    }
+
+    pub fn set_resolution(&mut self, registry: &Registry, bits: u8) {
+        let gauge = self.resolution.get_or_insert_with(|| {
+            let opts = Opts::new(
+                "temperature_sensor_resolution_bits",
+                "Effective conversion resolution of the sensor in bits",
+            )
+            .const_labels(self.labels.clone());
+            let gauge = IntGauge::with_opts(opts).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        });
+        gauge.set(bits.into());
+    }
+
+    pub fn set_hw_alarm(&mut self, registry: &Registry, alarm: bool) {
+        let gauge = self.hw_alarm.get_or_insert_with(|| {
+            let opts = Opts::new(
+                "temperature_sensor_hw_alarm",
+                "Whether the sensor reported a TH/TL alarm in the last alarm search",
+            )
+            .const_labels(self.labels.clone());
+            let gauge = IntGauge::with_opts(opts).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        });
+        gauge.set(alarm.into());
+    }
+
+    pub fn set_external_power(&mut self, registry: &Registry, external: bool) {
+        let gauge = self.external_power.get_or_insert_with(|| {
+            let opts = Opts::new(
+                "temperature_sensor_external_power",
+                "Whether the sensor is externally powered (1) or parasite powered (0)",
+            )
+            .const_labels(self.labels.clone());
+            let gauge = IntGauge::with_opts(opts).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        });
+        gauge.set(external.into());
+    }
+
+    pub fn set_present(&self, present: bool) {
+        self.present.set(present.into());
+    }
+
+    /// Removes every collector of the sensor from the registry.
+    pub fn unregister(mut self, registry: &Registry) {
+        self.clear_readings(registry);
+        let optional = [self.resolution, self.hw_alarm, self.external_power];
+        for gauge in optional.into_iter().flatten() {
+            unregister(registry, gauge);
+        }
+        unregister(registry, self.present);
+        unregister(registry, self.last_success);
+        unregister(registry, self.read_errors);
+        unregister(registry, self.read_duration);
+        unregister(registry, self.crc_failures);
+        unregister(registry, self.invalid_readings);
+    }
+
+    pub fn observe_read_duration(&self, duration: Duration) {
+        self.read_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn inc_read_errors(&self, kind: &str) {
+        self.read_errors.with_label_values(&[kind]).inc();
+    }
+
+    pub fn inc_crc_failures(&self, count: u32) {
+        self.crc_failures.inc_by(count.into());
+    }
+
+    pub fn inc_invalid_readings(&self, reason: &str) {
+        self.invalid_readings.with_label_values(&[reason]).inc();
+    }
+}
+
+/// Per-bus metrics read from the bus masters' sysfs attributes.
+pub struct BusMetrics {
+    slaves: IntGaugeVec,
+    search_attempts: IntGaugeVec,
+    search: IntGaugeVec,
+}
+
+impl BusMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        let gauge = |name: &str, help: &str| {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), &["bus"]).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+        Self {
+            slaves: gauge(
+                "w1_bus_slaves",
+                "Number of slaves found by the last search (w1_master_slave_count)",
+            ),
+            search_attempts: gauge(
+                "w1_bus_search_attempts",
+                "Searches run on the bus since it was registered (w1_master_attempts)",
+            ),
+            search: gauge(
+                "w1_bus_search",
+                "Remaining automatic searches, -1 for unlimited (w1_master_search)",
+            ),
+        }
+    }
+
+    pub fn set(&self, bus: &str, stats: &BusStats) {
+        self.slaves
+            .with_label_values(&[bus])
+            .set(stats.slave_count as i64);
+        self.search_attempts
+            .with_label_values(&[bus])
+            .set(stats.attempts as i64);
+        self.search.with_label_values(&[bus]).set(stats.search);
+    }
+
+    /// Drops the series of a bus master that went away.
+    pub fn remove(&self, bus: &str) {
+        for gauge in [&self.slaves, &self.search_attempts, &self.search] {
+            let _ = gauge.remove_label_values(&[bus]);
+        }
+    }
+}
+
+/// Labels identifying a sensor on every series it exports.
+pub fn sensor_labels(sensor: &Sensor) -> HashMap<String, String> {
+    HashMap::from([
+        ("sensor".to_string(), sensor.id.clone()),
+        ("family".to_string(), sensor.family.clone()),
+        ("chip".to_string(), sensor.chip.clone()),
+        ("bus".to_string(), sensor.bus_name().to_string()),
+        ("source".to_string(), sensor.source.to_string()),
+    ])
+}
+
+fn unregister(registry: &Registry, collector: impl Collector + 'static) {
+    if let Err(e) = registry.unregister(Box::new(collector)) {
+        eprintln!("Failed to unregister collector: {}", e);
+    }
+}
@@ -0,0 +1,346 @@
+//! The owserver network protocol of OWFS.
+//!
+//! Every message starts with a header of six big-endian 32-bit integers,
+//! followed by `payload` bytes. Requests carry a NUL-terminated path, plus
+//! the data for writes. owserver answers a request with one response, or
+//! with one response per entry for `DIR`, and may send keepalive headers
+//! with a payload length of -1 while it is busy.
+
+mod server;
+
+pub use server::serve;
+
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv6Addr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+pub const DEFAULT_PORT: u16 = 4304;
+
+pub const MSG_NOP: i32 = 1;
+pub const MSG_READ: i32 = 2;
+pub const MSG_WRITE: i32 = 3;
+pub const MSG_DIR: i32 = 4;
+pub const MSG_PRESENCE: i32 = 6;
+pub const MSG_DIRALL: i32 = 7;
+pub const MSG_GET: i32 = 8;
+pub const MSG_DIRALLSLASH: i32 = 9;
+pub const MSG_GETSLASH: i32 = 10;
+
+/// Flag asking the server to keep the connection open for another request.
+pub const FLAG_PERSISTENCE: i32 = 0x04;
+/// Flag marking a client that understands the owserver protocol rather
+/// than the older OWFS one.
+pub const FLAG_OWNET: i32 = 0x100;
+
+/// The largest read answer we ask for.
+const READ_SIZE: i32 = 8192;
+/// The largest directory listing we accept.
+const MAX_LISTING: i32 = 65536;
+
+/// The header of a request or response. In responses `kind` holds the
+/// return value: the number of bytes for reads, or a negative errno.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Header {
+    pub version: i32,
+    pub payload: i32,
+    pub kind: i32,
+    pub flags: i32,
+    pub size: i32,
+    pub offset: i32,
+}
+
+impl Header {
+    pub const LEN: usize = 24;
+
+    pub fn parse(buf: &[u8; Self::LEN]) -> Self {
+        let field = |i: usize| i32::from_be_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+        Self {
+            version: field(0),
+            payload: field(1),
+            kind: field(2),
+            flags: field(3),
+            size: field(4),
+            offset: field(5),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut buf = [0; Self::LEN];
+        let fields = [
+            self.version,
+            self.payload,
+            self.kind,
+            self.flags,
+            self.size,
+            self.offset,
+        ];
+        for (chunk, field) in buf.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_be_bytes());
+        }
+        buf
+    }
+
+    /// Whether this is a keepalive sent while the server is busy.
+    pub fn is_keepalive(&self) -> bool {
+        self.payload == -1
+    }
+}
+
+/// The text of a payload up to its terminating NUL byte.
+pub fn nul_terminated(payload: &[u8]) -> String {
+    let end = payload
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(payload.len());
+    String::from_utf8_lossy(&payload[..end]).into_owned()
+}
+
+/// A connection-per-request owserver client.
+pub struct Client {
+    address: String,
+    timeout: Duration,
+}
+
+impl Client {
+    /// `address` is `host:port`, or `[addr]:port` for IPv6; the port
+    /// defaults to 4304.
+    pub fn new(address: &str, timeout: Duration) -> Self {
+        let has_port = match address.strip_prefix('[') {
+            Some(rest) => rest.contains("]:"),
+            None => address.contains(':') && address.parse::<Ipv6Addr>().is_err(),
+        };
+        let address = if has_port {
+            address.to_string()
+        } else if address.parse::<Ipv6Addr>().is_ok() {
+            format!("[{}]:{}", address, DEFAULT_PORT)
+        } else {
+            format!("{}:{}", address, DEFAULT_PORT)
+        };
+        Self { address, timeout }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Lists the entries of the directory `path`, as full paths.
+    pub fn dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let data = self.request(MSG_DIRALL, path, 0)?;
+        let list = nul_terminated(&data);
+        Ok(list
+            .split(',')
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Reads the property at `path`.
+    pub fn read(&self, path: &str) -> io::Result<String> {
+        let data = self.request(MSG_READ, path, READ_SIZE)?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    fn request(&self, kind: i32, path: &str, size: i32) -> io::Result<Vec<u8>> {
+        let address = self.address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", self.address),
+            )
+        })?;
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut payload = path.as_bytes().to_vec();
+        payload.push(0);
+        let header = Header {
+            version: 0,
+            payload: payload.len() as i32,
+            kind,
+            flags: FLAG_OWNET,
+            size,
+            offset: 0,
+        };
+        stream.write_all(&header.to_bytes())?;
+        stream.write_all(&payload)?;
+
+        let response = loop {
+            let mut buf = [0; Header::LEN];
+            stream.read_exact(&mut buf)?;
+            let response = Header::parse(&buf);
+            if !response.is_keepalive() {
+                break response;
+            }
+        };
+        if response.kind < 0 {
+            return Err(io::Error::from_raw_os_error(-response.kind));
+        }
+        let limit = if kind == MSG_READ {
+            READ_SIZE
+        } else {
+            MAX_LISTING
+        };
+        if response.payload > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "answer of {} bytes exceeds {} bytes",
+                    response.payload, limit
+                ),
+            ));
+        }
+        let mut data = vec![0; response.payload.max(0) as usize];
+        stream.read_exact(&mut data)?;
+        if kind == MSG_READ {
+            data.truncate(response.size.max(0) as usize);
+        }
+        Ok(data)
+    }
+}
+
+/// An in-process owserver for tests, answering from a closure.
+#[cfg(test)]
+pub mod fake {
+    use super::Header;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Starts a server on a free local port and returns its address. Every
+    /// request is answered with the messages `respond` returns for its
+    /// header and path, then the connection is closed.
+    pub fn serve(respond: impl Fn(&Header, &str) -> Vec<Vec<u8>> + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; Header::LEN];
+                if stream.read_exact(&mut buf).is_err() {
+                    continue;
+                }
+                let request = Header::parse(&buf);
+                let mut payload = vec![0; request.payload as usize];
+                stream.read_exact(&mut payload).unwrap();
+                for message in respond(&request, &super::nul_terminated(&payload)) {
+                    stream.write_all(&message).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    /// A response message with return value `ret` and `data` as payload.
+    pub fn message(ret: i32, data: &[u8]) -> Vec<u8> {
+        let header = Header {
+            payload: data.len() as i32,
+            kind: ret,
+            size: data.len() as i32,
+            ..Header::default()
+        };
+        let mut message = header.to_bytes().to_vec();
+        message.extend_from_slice(data);
+        message
+    }
+
+    /// A keepalive header, as sent by a busy server.
+    pub fn keepalive() -> Vec<u8> {
+        let header = Header {
+            payload: -1,
+            ..Header::default()
+        };
+        header.to_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn header_round_trips() {
+        let header = Header {
+            version: 0,
+            payload: 12,
+            kind: MSG_READ,
+            flags: FLAG_OWNET | FLAG_PERSISTENCE,
+            size: READ_SIZE,
+            offset: -3,
+        };
+        assert_eq!(Header::parse(&header.to_bytes()), header);
+    }
+
+    #[test]
+    fn adds_default_port() {
+        let address = |address| Client::new(address, TIMEOUT).address;
+        assert_eq!(address("localhost"), "localhost:4304");
+        assert_eq!(address("pi.local:4305"), "pi.local:4305");
+        assert_eq!(address("::1"), "[::1]:4304");
+        assert_eq!(address("[::1]"), "[::1]:4304");
+        assert_eq!(address("[fe80::1]:4305"), "[fe80::1]:4305");
+    }
+
+    #[test]
+    fn lists_directories() {
+        let address = fake::serve(|request, path| {
+            assert_eq!(request.kind, MSG_DIRALL);
+            assert_eq!(path, "/uncached/");
+            vec![fake::message(
+                0,
+                b"/uncached/28.0316A2791AFF,/uncached/10.A8F4E2000800\0",
+            )]
+        });
+        let entries = Client::new(&address, TIMEOUT).dir("/uncached/").unwrap();
+        assert_eq!(
+            entries,
+            ["/uncached/28.0316A2791AFF", "/uncached/10.A8F4E2000800"]
+        );
+    }
+
+    #[test]
+    fn reads_after_keepalives() {
+        let address = fake::serve(|request, path| {
+            assert_eq!(request.kind, MSG_READ);
+            assert_eq!(path, "/28.0316A2791AFF/temperature");
+            vec![
+                fake::keepalive(),
+                fake::keepalive(),
+                fake::message(11, b"     23.125"),
+            ]
+        });
+        let value = Client::new(&address, TIMEOUT)
+            .read("/28.0316A2791AFF/temperature")
+            .unwrap();
+        assert_eq!(value.trim(), "23.125");
+    }
+
+    #[test]
+    fn reports_negative_errno() {
+        let address = fake::serve(|_, _| vec![fake::message(-libc::ENOENT, b"")]);
+        let error = Client::new(&address, TIMEOUT)
+            .read("/28.0316A2791AFF/temperature")
+            .unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn rejects_oversized_answers() {
+        let address = fake::serve(|_, _| {
+            let header = Header {
+                payload: i32::MAX,
+                ..Header::default()
+            };
+            vec![header.to_bytes().to_vec()]
+        });
+        let error = Client::new(&address, TIMEOUT)
+            .read("/28.0316A2791AFF/temperature")
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,387 @@
+use super::{
+    nul_terminated, Header, FLAG_PERSISTENCE, MSG_DIR, MSG_DIRALL, MSG_DIRALLSLASH, MSG_GET,
+    MSG_GETSLASH, MSG_NOP, MSG_PRESENCE, MSG_READ, MSG_WRITE,
+};
+use crate::AppState;
+use std::{collections::BTreeMap, io, net::SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Requests carry a path; anything much longer than that is not a request.
+const MAX_PAYLOAD: i32 = 65536;
+
+/// Properties of every device directory.
+const PROPERTIES: &[&str] = &["family", "id", "temperature", "type"];
+
+/// A sensor as OWFS clients see it.
+struct Device {
+    family: String,
+    /// The serial number, the part of the name after the family code.
+    serial: String,
+    chip: String,
+    temperature: Option<f64>,
+}
+
+/// What a request path points at.
+enum Node<'a> {
+    Root,
+    Device,
+    Property(&'a Device, &'a str),
+}
+
+/// One response message: the return value and the payload.
+struct Response {
+    ret: i32,
+    data: Vec<u8>,
+    /// Length of the data without the terminating NUL of text payloads.
+    size: usize,
+}
+
+impl Response {
+    fn error(errno: i32) -> Self {
+        Self {
+            ret: -errno,
+            data: Vec::new(),
+            size: 0,
+        }
+    }
+
+    fn ok() -> Self {
+        Self {
+            ret: 0,
+            data: Vec::new(),
+            size: 0,
+        }
+    }
+
+    /// A NUL-terminated text payload, as used for directory listings.
+    fn text(text: &str) -> Self {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        Self {
+            ret: 0,
+            size: text.len(),
+            data,
+        }
+    }
+}
+
+/// Answers owserver protocol requests on `listen` from the readings the
+/// poller keeps in `state`, so OWFS clients can read the sensors without
+/// competing with us for the bus. Writes are refused.
+pub async fn serve(listen: SocketAddr, state: AppState) -> io::Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    println!("Starting owserver on {}", listen);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Failed to accept owserver connection: {}", e);
+                continue;
+            }
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &state).await {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    eprintln!("owserver connection from {} failed: {}", peer, e);
+                }
+            }
+        });
+    }
+}
+
+/// Answers the requests of one connection. The connection is closed after
+/// the first request unless the client asks for persistence.
+async fn handle(mut stream: TcpStream, state: &AppState) -> io::Result<()> {
+    loop {
+        let mut buf = [0; Header::LEN];
+        stream.read_exact(&mut buf).await?;
+        let request = Header::parse(&buf);
+        if !(0..=MAX_PAYLOAD).contains(&request.payload) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid payload length {}", request.payload),
+            ));
+        }
+        let mut payload = vec![0; request.payload as usize];
+        stream.read_exact(&mut payload).await?;
+        let path = nul_terminated(&payload);
+
+        let responses = respond(&request, &path, &devices(state));
+        for response in responses {
+            let header = Header {
+                version: 0,
+                payload: response.data.len() as i32,
+                kind: response.ret,
+                flags: request.flags,
+                size: response.size as i32,
+                offset: 0,
+            };
+            stream.write_all(&header.to_bytes()).await?;
+            stream.write_all(&response.data).await?;
+        }
+
+        if request.flags & FLAG_PERSISTENCE == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// The 1-Wire sensors currently on the bus, keyed by their OWFS name.
+fn devices(state: &AppState) -> BTreeMap<String, Device> {
+    state
+        .sensors
+        .read()
+        .values()
+        .filter(|tracked| tracked.present)
+        .filter(|tracked| matches!(tracked.sensor.source, "w1" | "owserver"))
+        .map(|tracked| {
+            let name = owfs_name(&tracked.sensor.id);
+            let device = Device {
+                family: tracked.sensor.family.to_uppercase(),
+                serial: name
+                    .split_once('.')
+                    .map_or(String::new(), |(_, serial)| serial.to_string()),
+                chip: tracked.sensor.chip.clone(),
+                temperature: tracked.metrics.temperature(),
+            };
+            (name, device)
+        })
+        .collect()
+}
+
+/// The OWFS name of a sensor: `28-0316a2791aff` becomes `28.0316A2791AFF`.
+/// IDs of sensors read from an owserver already have this form.
+fn owfs_name(id: &str) -> String {
+    id.replacen('-', ".", 1).to_uppercase()
+}
+
+fn resolve<'a>(path: &'a str, devices: &'a BTreeMap<String, Device>) -> Option<Node<'a>> {
+    let mut parts = path.split('/').filter(|part| !part.is_empty()).peekable();
+    if parts.peek() == Some(&"uncached") {
+        parts.next();
+    }
+    let node = match (parts.next(), parts.next()) {
+        (None, _) => Node::Root,
+        (Some(name), None) => devices
+            .contains_key(&name.to_uppercase())
+            .then_some(Node::Device)?,
+        (Some(name), Some(property)) => {
+            let property = PROPERTIES.iter().find(|p| **p == property)?;
+            Node::Property(devices.get(&name.to_uppercase())?, property)
+        }
+    };
+    parts.next().is_none().then_some(node)
+}
+
+fn respond(request: &Header, path: &str, devices: &BTreeMap<String, Device>) -> Vec<Response> {
+    let node = resolve(path, devices);
+    match (request.kind, node) {
+        (MSG_NOP, _) => vec![Response::ok()],
+        (MSG_WRITE, _) => vec![Response::error(libc::EROFS)],
+        (_, None) => vec![Response::error(libc::ENOENT)],
+        (MSG_PRESENCE, Some(_)) => vec![Response::ok()],
+        (MSG_READ | MSG_GET | MSG_GETSLASH, Some(Node::Property(device, property))) => {
+            vec![read(request, device, property)]
+        }
+        (MSG_READ, Some(_)) => vec![Response::error(libc::EISDIR)],
+        (MSG_DIR | MSG_DIRALL | MSG_DIRALLSLASH, Some(Node::Property(..))) => {
+            vec![Response::error(libc::ENOTDIR)]
+        }
+        (MSG_DIR, Some(node)) => {
+            let mut responses: Vec<Response> = entries(path, &node, devices, false)
+                .iter()
+                .map(|entry| Response::text(entry))
+                .collect();
+            // An empty message ends the listing.
+            responses.push(Response::ok());
+            responses
+        }
+        (MSG_DIRALL | MSG_GET, Some(node)) => {
+            vec![Response::text(
+                &entries(path, &node, devices, false).join(","),
+            )]
+        }
+        (MSG_DIRALLSLASH | MSG_GETSLASH, Some(node)) => {
+            vec![Response::text(
+                &entries(path, &node, devices, true).join(","),
+            )]
+        }
+        _ => vec![Response::error(libc::ENOTSUP)],
+    }
+}
+
+/// Full paths of the entries of a directory node. With `slash`,
+/// directories get a trailing slash.
+fn entries(
+    path: &str,
+    node: &Node,
+    devices: &BTreeMap<String, Device>,
+    slash: bool,
+) -> Vec<String> {
+    let base = path.trim_end_matches('/');
+    match node {
+        Node::Root => devices
+            .keys()
+            .map(|name| format!("{}/{}{}", base, name, if slash { "/" } else { "" }))
+            .collect(),
+        Node::Device => PROPERTIES
+            .iter()
+            .map(|property| format!("{}/{}", base, property))
+            .collect(),
+        Node::Property(..) => Vec::new(),
+    }
+}
+
+fn read(request: &Header, device: &Device, property: &str) -> Response {
+    let value = match property {
+        "family" => device.family.clone(),
+        "id" => device.serial.clone(),
+        // OWFS names a single part per family.
+        "type" => device
+            .chip
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        "temperature" => match device.temperature {
+            Some(temp) => format!("{:12.4}", temp),
+            None => return Response::error(libc::ENODATA),
+        },
+        _ => return Response::error(libc::ENOENT),
+    };
+    let offset = (request.offset.max(0) as usize).min(value.len());
+    let mut data = value.as_bytes()[offset..].to_vec();
+    if request.size >= 0 {
+        data.truncate(request.size as usize);
+    }
+    Response {
+        ret: data.len() as i32,
+        size: data.len(),
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> BTreeMap<String, Device> {
+        let device = |serial: &str, temperature| Device {
+            family: "28".to_string(),
+            serial: serial.to_string(),
+            chip: "DS18B20".to_string(),
+            temperature,
+        };
+        BTreeMap::from([
+            (
+                "28.0316A2791AFF".to_string(),
+                device("0316A2791AFF", Some(23.125)),
+            ),
+            ("28.000000000001".to_string(), device("000000000001", None)),
+        ])
+    }
+
+    fn request(kind: i32) -> Header {
+        Header {
+            kind,
+            size: 8192,
+            ..Header::default()
+        }
+    }
+
+    fn text(response: &Response) -> &str {
+        std::str::from_utf8(&response.data[..response.size]).unwrap()
+    }
+
+    #[test]
+    fn names_sensors_like_owfs() {
+        assert_eq!(owfs_name("28-0316a2791aff"), "28.0316A2791AFF");
+        assert_eq!(owfs_name("28.0316A2791AFF"), "28.0316A2791AFF");
+    }
+
+    #[test]
+    fn resolves_paths() {
+        let devices = devices();
+        assert!(matches!(resolve("/", &devices), Some(Node::Root)));
+        assert!(matches!(resolve("/uncached", &devices), Some(Node::Root)));
+        assert!(matches!(
+            resolve("/28.0316a2791aff/", &devices),
+            Some(Node::Device)
+        ));
+        assert!(matches!(
+            resolve("/uncached/28.0316A2791AFF/temperature", &devices),
+            Some(Node::Property(device, "temperature")) if device.serial == "0316A2791AFF"
+        ));
+        assert!(resolve("/28.FFFFFFFFFFFF", &devices).is_none());
+        assert!(resolve("/28.0316A2791AFF/power", &devices).is_none());
+        assert!(resolve("/28.0316A2791AFF/temperature/x", &devices).is_none());
+    }
+
+    #[test]
+    fn lists_directories() {
+        let devices = devices();
+        let responses = respond(&request(MSG_DIR), "/", &devices);
+        let entries: Vec<&str> = responses.iter().map(text).collect();
+        assert_eq!(entries, ["/28.000000000001", "/28.0316A2791AFF", ""]);
+
+        let responses = respond(&request(MSG_DIRALL), "/28.0316A2791AFF", &devices);
+        assert_eq!(
+            text(&responses[0]),
+            "/28.0316A2791AFF/family,/28.0316A2791AFF/id,\
+             /28.0316A2791AFF/temperature,/28.0316A2791AFF/type"
+        );
+
+        let responses = respond(&request(MSG_DIRALLSLASH), "/uncached/", &devices);
+        assert_eq!(
+            text(&responses[0]),
+            "/uncached/28.000000000001/,/uncached/28.0316A2791AFF/"
+        );
+    }
+
+    #[test]
+    fn reads_properties() {
+        let devices = devices();
+        let read = |path| {
+            let responses = respond(&request(MSG_READ), path, &devices);
+            assert_eq!(responses.len(), 1);
+            text(&responses[0]).to_string()
+        };
+        assert_eq!(read("/28.0316A2791AFF/temperature"), "     23.1250");
+        assert_eq!(read("/28.0316A2791AFF/family"), "28");
+        assert_eq!(read("/28.0316A2791AFF/id"), "0316A2791AFF");
+        assert_eq!(read("/28.0316A2791AFF/type"), "DS18B20");
+    }
+
+    #[test]
+    fn applies_offset_and_size() {
+        let request = Header {
+            offset: 5,
+            size: 4,
+            ..request(MSG_READ)
+        };
+        let responses = respond(&request, "/28.0316A2791AFF/temperature", &devices());
+        assert_eq!(text(&responses[0]), "23.1");
+        assert_eq!(responses[0].ret, 4);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let devices = devices();
+        let ret = |kind, path| respond(&request(kind), path, &devices)[0].ret;
+        assert_eq!(ret(MSG_READ, "/28.FFFFFFFFFFFF/temperature"), -libc::ENOENT);
+        assert_eq!(ret(MSG_READ, "/28.0316A2791AFF"), -libc::EISDIR);
+        assert_eq!(ret(MSG_DIR, "/28.0316A2791AFF/id"), -libc::ENOTDIR);
+        assert_eq!(
+            ret(MSG_READ, "/28.000000000001/temperature"),
+            -libc::ENODATA
+        );
+        assert_eq!(ret(MSG_WRITE, "/28.0316A2791AFF/temperature"), -libc::EROFS);
+        assert_eq!(ret(99, "/"), -libc::ENOTSUP);
+        assert_eq!(ret(MSG_PRESENCE, "/28.0316A2791AFF"), 0);
+        assert_eq!(ret(MSG_NOP, ""), 0);
+    }
+}
@@ -0,0 +1,917 @@
+use crate::{
+    config::Config,
+    hotplug,
+    metrics::{BusMetrics, SensorMetrics},
+    schedule::Schedule,
+    source::{Measurement, Metadata, ReadError, Sensor, SensorSource, Target},
+    supervisor::{self, BusSupervisor},
+    w1, AppState,
+};
+use parking_lot::Mutex;
+use prometheus::{IntGauge, Opts};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task::{self, JoinSet},
+    time,
+};
+
+/// A sensor the poller has seen on the bus at least once.
+pub struct TrackedSensor {
+    /// The sensor as reported by its source.
+    pub sensor: Sensor,
+    pub metrics: SensorMetrics,
+    /// The w1 bus master the sensor was discovered on.
+    pub bus: Option<String>,
+    /// When the sensor was last reported by its source.
+    pub last_seen: Instant,
+    /// When the sensor was last read successfully.
+    pub last_success: Option<Instant>,
+    /// Whether the sensor was on the bus during the last poll.
+    pub present: bool,
+}
+
+/// The result of reading one sensor, produced on the blocking pool and
+/// applied to the metrics afterwards.
+pub struct ReadOutcome {
+    pub target: Target,
+    pub result: Result<Measurement, ReadError>,
+    pub crc_failures: u32,
+    /// How long the read took, or `None` if it was not attempted because
+    /// the previous read of the sensor or every other read is still hanging.
+    pub duration: Option<Duration>,
+}
+
+impl ReadOutcome {
+    fn timed_out(target: Target, duration: Option<Duration>) -> Self {
+        Self {
+            target,
+            result: Err(ReadError::Timeout),
+            crc_failures: 0,
+            duration,
+        }
+    }
+}
+
+/// What the poll loop knows about one source.
+struct SourceState {
+    source: Arc<dyn SensorSource>,
+    /// The sensors as of the last discovery plus the hotplug events since.
+    sensors: Vec<Sensor>,
+    /// Whether `sensors` is current, i.e. the last discovery succeeded.
+    listed: bool,
+    last_discovery: Option<Instant>,
+    /// Set while hotplug events of the source are being received.
+    resync_interval: Option<Duration>,
+}
+
+impl SourceState {
+    fn targets(&self) -> impl Iterator<Item = Target> + '_ {
+        self.sensors.iter().map(|sensor| Target {
+            source: self.source.clone(),
+            sensor: sensor.clone(),
+        })
+    }
+}
+
+/// State the poll loop keeps between polls.
+struct Poller<'a> {
+    config: &'a Arc<Config>,
+    state: AppState,
+    present_count: IntGauge,
+    bus_metrics: BusMetrics,
+    supervisor: BusSupervisor,
+    /// Bus masters seen during the previous poll.
+    buses: HashSet<String>,
+    in_flight: InFlight,
+    /// When the metadata of every sensor was last read, by source name.
+    last_metadata: HashMap<String, Instant>,
+    /// Buses with a recovery action still running.
+    recovering: Arc<Mutex<HashSet<String>>>,
+}
+
+impl<'a> Poller<'a> {
+    fn new(config: &'a Arc<Config>, state: AppState) -> Self {
+        let opts = Opts::new(
+            "temperature_sensors_present",
+            "Number of polled sensors found on the bus during the last poll",
+        );
+        let present_count = IntGauge::with_opts(opts).unwrap();
+        state
+            .registry
+            .register(Box::new(present_count.clone()))
+            .unwrap();
+        Self {
+            config,
+            bus_metrics: BusMetrics::new(&state.registry),
+            supervisor: BusSupervisor::new(&state.registry),
+            state,
+            present_count,
+            buses: HashSet::new(),
+            in_flight: InFlight::new(config),
+            last_metadata: HashMap::new(),
+            recovering: Arc::default(),
+        }
+    }
+
+    /// Reads the sensors in `due` plus any sensor in `present` that was not
+    /// seen before. `present` lists every sensor currently available from
+    /// all sources; tracked sensors missing from it are marked absent and
+    /// eventually expired. Only a `sweep`, a scheduled poll at the global
+    /// interval, counts towards the failed polls of the bus supervisor.
+    async fn poll(&mut self, present: &[Target], due: Vec<Target>, sweep: bool) {
+        let config: &Config = self.config;
+        let new: Vec<Target> = {
+            let tracked = self.state.sensors.read();
+            present
+                .iter()
+                .filter(|target| !tracked.contains_key(&target.sensor.id))
+                .cloned()
+                .collect()
+        };
+
+        let mut metadata_due = HashSet::new();
+        for target in present {
+            let name = target.source.name();
+            let Some(interval) = target.source.metadata_interval() else {
+                continue;
+            };
+            if self
+                .last_metadata
+                .get(name)
+                .is_none_or(|checked| checked.elapsed() >= interval)
+            {
+                metadata_due.insert(name.to_string());
+            }
+        }
+        let now = Instant::now();
+        for name in &metadata_due {
+            self.last_metadata.insert(name.clone(), now);
+        }
+        let inspect: Vec<Target> = present
+            .iter()
+            .filter(|target| {
+                metadata_due.contains(target.source.name())
+                    || new.iter().any(|t| t.sensor.id == target.sensor.id)
+            })
+            .cloned()
+            .collect();
+
+        let mut to_read = due;
+        for target in &new {
+            if !to_read.iter().any(|t| t.sensor.id == target.sensor.id) {
+                to_read.push(target.clone());
+            }
+        }
+        setup_sensors(new).await;
+        let metadata = read_metadata(inspect).await;
+        prepare_sources(&to_read).await;
+        let outcomes = read_sensors(config, &self.in_flight, to_read).await;
+
+        let now = Instant::now();
+        let state = &self.state;
+        let present: HashSet<String> = present
+            .iter()
+            .map(|target| target.sensor.id.clone())
+            .collect();
+        let actions = {
+            let mut tracked = state.sensors.write();
+            let read = apply_outcomes(config, state, &mut tracked, outcomes, now);
+            for (id, metadata) in metadata {
+                if let Some(sensor) = tracked.get_mut(&id) {
+                    if let Some(bits) = metadata.resolution {
+                        sensor.metrics.set_resolution(&state.registry, bits);
+                    }
+                    if let Some(external) = metadata.external_power {
+                        sensor.metrics.set_external_power(&state.registry, external);
+                    }
+                }
+            }
+            expire_sensors(config, state, &mut tracked, &present, now);
+            self.present_count
+                .set(tracked.values().filter(|sensor| sensor.present).count() as i64);
+
+            if config.supervisor.enabled && sweep {
+                let recovering = self.recovering.lock();
+                self.supervisor
+                    .record_poll(&config.supervisor, &tracked, &read, &recovering, now)
+            } else {
+                Vec::new()
+            }
+        };
+
+        // Recovery commands may take a while; the poll loop must not wait
+        // for them.
+        for (bus, action) in actions {
+            self.recovering.lock().insert(bus.clone());
+            let config = Arc::clone(self.config);
+            let recovering = self.recovering.clone();
+            tokio::spawn(async move {
+                supervisor::run_action(&config, &bus, action).await;
+                recovering.lock().remove(&bus);
+            });
+        }
+    }
+
+    /// Marks the sensor `id` as absent right away after it was detached.
+    fn remove(&self, id: &str) {
+        let mut tracked = self.state.sensors.write();
+        if let Some(sensor) = tracked.get_mut(id) {
+            sensor.present = false;
+            sensor.metrics.set_present(false);
+        }
+        self.present_count
+            .set(tracked.values().filter(|sensor| sensor.present).count() as i64);
+    }
+}
+
+pub async fn update_temperatures(
+    config: &Arc<Config>,
+    sources: Vec<Arc<dyn SensorSource>>,
+    state: AppState,
+) {
+    let mut poller = Poller::new(config, state);
+
+    // Hotplug events of all watched sources, tagged with the index of the
+    // source. `None` tells that the watcher of the source stopped.
+    let (events_tx, mut events) = mpsc::channel(64);
+    let mut sources: Vec<SourceState> = sources
+        .into_iter()
+        .enumerate()
+        .map(|(index, source)| {
+            let resync_interval = match source.watch() {
+                Ok(Some(mut watcher)) => {
+                    let events_tx = events_tx.clone();
+                    tokio::spawn(async move {
+                        while let Some(event) = watcher.events.recv().await {
+                            if events_tx.send((index, Some(event))).await.is_err() {
+                                return;
+                            }
+                        }
+                        let _ = events_tx.send((index, None)).await;
+                    });
+                    Some(watcher.resync_interval)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to watch {}, listing it every poll instead: {}",
+                        source.name(),
+                        e
+                    );
+                    None
+                }
+            };
+            SourceState {
+                source,
+                sensors: Vec::new(),
+                listed: false,
+                last_discovery: None,
+                resync_interval,
+            }
+        })
+        .collect();
+    drop(events_tx);
+    let mut schedule = Schedule::new(config.poll_intervals());
+
+    loop {
+        let due = schedule.take_due(SystemTime::now());
+        if !due.is_empty() {
+            for source in &mut sources {
+                discover(source).await;
+            }
+            let present: Vec<Target> = sources.iter().flat_map(SourceState::targets).collect();
+            let due_targets = sources
+                .iter()
+                .filter(|source| source.listed)
+                .flat_map(SourceState::targets)
+                .filter(|target| due.contains(&config.poll_interval(&target.sensor.id)))
+                .collect();
+            let sweep = due.contains(&config.interval);
+            poller.poll(&present, due_targets, sweep).await;
+            if config.w1.enabled && sweep {
+                update_bus_metrics(config, &poller.bus_metrics, &mut poller.buses).await;
+            }
+        }
+
+        let deadline = time::Instant::now() + schedule.until_next();
+        loop {
+            let (index, event) = tokio::select! {
+                _ = time::sleep_until(deadline) => break,
+                Some(event) = events.recv() => event,
+            };
+            let source = &mut sources[index];
+            match event {
+                Some(hotplug::Event::Added(sensor)) => {
+                    if !config.sensor(&sensor.id).enabled
+                        || source.sensors.iter().any(|s| s.id == sensor.id)
+                    {
+                        continue;
+                    }
+                    println!("Sensor {} attached to {}", sensor.id, sensor.bus_name());
+                    source.sensors.push(sensor.clone());
+                    source.sensors.sort_by(|a, b| a.id.cmp(&b.id));
+                    let target = Target {
+                        source: source.source.clone(),
+                        sensor,
+                    };
+                    let present: Vec<Target> =
+                        sources.iter().flat_map(SourceState::targets).collect();
+                    poller.poll(&present, vec![target], false).await;
+                }
+                Some(hotplug::Event::Removed(id)) => {
+                    if let Some(index) = source.sensors.iter().position(|s| s.id == id) {
+                        println!("Sensor {} detached", id);
+                        source.sensors.remove(index);
+                        poller.remove(&id);
+                    }
+                }
+                Some(hotplug::Event::Overflow) => {
+                    eprintln!(
+                        "Hotplug events of {} were lost, listing it again",
+                        source.source.name()
+                    );
+                    source.last_discovery = None;
+                }
+                None => {
+                    eprintln!(
+                        "Hotplug watcher of {} stopped, listing it every poll",
+                        source.source.name()
+                    );
+                    source.resync_interval = None;
+                }
+            }
+        }
+    }
+}
+
+/// Lists the sensors of `source` unless its hotplug events keep the list
+/// current. When listing fails, the previous list is kept so the sensors
+/// are not reported absent, but `listed` is cleared so they are not read.
+async fn discover(source: &mut SourceState) {
+    let discovery_due = source.resync_interval.is_none_or(|interval| {
+        source
+            .last_discovery
+            .is_none_or(|discovered| discovered.elapsed() >= interval)
+    });
+    if !discovery_due {
+        source.listed = true;
+        return;
+    }
+
+    let discovering = source.source.clone();
+    match task::spawn_blocking(move || discovering.discover())
+        .await
+        .expect("discovery task panicked")
+    {
+        Ok(sensors) => {
+            source.sensors = sensors;
+            source.listed = true;
+            source.last_discovery = Some(Instant::now());
+        }
+        Err(e) => {
+            eprintln!("Failed to discover {} sensors: {}", source.source.name(), e);
+            source.listed = false;
+        }
+    }
+}
+
+/// Prepares newly discovered sensors for their first read.
+async fn setup_sensors(targets: Vec<Target>) {
+    task::spawn_blocking(move || {
+        for target in targets {
+            if let Err(e) = target.source.setup(&target.sensor) {
+                eprintln!("Failed to configure {}: {}", target.sensor.id, e);
+            }
+        }
+    })
+    .await
+    .expect("setup task panicked")
+}
+
+/// Reads the metadata of `targets`.
+async fn read_metadata(targets: Vec<Target>) -> Vec<(String, Metadata)> {
+    task::spawn_blocking(move || {
+        targets
+            .into_iter()
+            .filter_map(|target| match target.source.metadata(&target.sensor) {
+                Ok(metadata) => Some((target.sensor.id, metadata)),
+                Err(e) => {
+                    eprintln!("Failed to read the details of {}: {}", target.sensor.id, e);
+                    None
+                }
+            })
+            .collect()
+    })
+    .await
+    .expect("metadata task panicked")
+}
+
+/// Calls `prepare` once on every source with a sensor in `targets`.
+pub async fn prepare_sources(targets: &[Target]) {
+    let mut sources: Vec<Arc<dyn SensorSource>> = Vec::new();
+    for target in targets {
+        if !sources
+            .iter()
+            .any(|source| Arc::ptr_eq(source, &target.source))
+        {
+            sources.push(target.source.clone());
+        }
+    }
+    task::spawn_blocking(move || {
+        for source in sources {
+            source.prepare();
+        }
+    })
+    .await
+    .expect("prepare task panicked")
+}
+
+/// Reads the counters of every bus master into `bus_metrics`. `buses` holds
+/// the bus masters seen in the previous poll, so the series of masters that
+/// went away can be dropped.
+async fn update_bus_metrics(
+    config: &Config,
+    bus_metrics: &BusMetrics,
+    buses: &mut HashSet<String>,
+) {
+    let devices_path = config.w1.devices_path.clone();
+    let stats = task::spawn_blocking(move || {
+        w1::bus_masters(&devices_path).map(|masters| {
+            masters
+                .into_iter()
+                .filter_map(|master| match master.stats() {
+                    Ok(stats) => Some((master.name, stats)),
+                    Err(e) => {
+                        eprintln!("Failed to read the counters of {}: {}", master.name, e);
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+    })
+    .await
+    .expect("bus metrics task panicked");
+
+    let stats = match stats {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Failed to list bus masters: {}", e);
+            return;
+        }
+    };
+
+    let current: HashSet<String> = stats.iter().map(|(name, _)| name.clone()).collect();
+    for gone in buses.difference(&current) {
+        bus_metrics.remove(gone);
+    }
+    for (name, stats) in &stats {
+        bus_metrics.set(name, stats);
+    }
+    *buses = current;
+}
+
+/// Periodically runs a hardware alarm search on every bus and updates the
+/// alarm state of the sensors that have thresholds configured. Unlike the
+/// read sweep, a search takes a few milliseconds regardless of the number of
+/// sensors.
+pub async fn search_alarms(config: &Config, interval: Duration, state: AppState) {
+    let mut ticker = time::interval(interval);
+    ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        let devices_path = config.w1.devices_path.clone();
+        let timeout = config.w1.read_timeout;
+        let alarming = task::spawn_blocking(move || -> std::io::Result<HashSet<String>> {
+            let mut alarming = HashSet::new();
+            for master in w1::bus_masters(&devices_path)? {
+                let Some(id) = master.id() else { continue };
+                let ids = w1::alarm_search(id, timeout).map_err(|e| {
+                    std::io::Error::new(e.kind(), format!("{}: {}", master.name, e))
+                })?;
+                alarming.extend(ids);
+            }
+            Ok(alarming)
+        })
+        .await
+        .expect("alarm search task panicked");
+
+        let alarming = match alarming {
+            Ok(alarming) => alarming,
+            Err(e) => {
+                // A partial result would clear the alarms of sensors on the
+                // failed bus, so skip this round entirely.
+                eprintln!("Alarm search failed: {}", e);
+                continue;
+            }
+        };
+
+        let mut tracked = state.sensors.write();
+        for (id, sensor) in tracked.iter_mut() {
+            if config.sensor(id).alarm_low.is_none() {
+                continue;
+            }
+            let alarm = alarming.contains(id);
+            if alarm {
+                println!("Hardware alarm on {}", id);
+            }
+            sensor.metrics.set_hw_alarm(&state.registry, alarm);
+        }
+    }
+}
+
+/// Reads still running on the blocking pool, shared by every poll. A read
+/// that timed out cannot be cancelled, so the sensor is skipped until its
+/// read returns instead of piling up blocked threads, and the read keeps its
+/// slot of `w1.max_concurrent_reads` until then.
+#[derive(Clone)]
+pub struct InFlight {
+    /// IDs of the sensors being read.
+    sensors: Arc<Mutex<HashSet<String>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl InFlight {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            sensors: Arc::default(),
+            permits: Arc::new(Semaphore::new(config.w1.max_concurrent_reads)),
+        }
+    }
+}
+
+/// Reads all `targets` on the blocking pool, at most
+/// `w1.max_concurrent_reads` at a time including abandoned reads that are
+/// still running. Reads that take longer than the sensor's read timeout are
+/// abandoned and reported as timeouts.
+pub async fn read_sensors(
+    config: &Config,
+    in_flight: &InFlight,
+    targets: Vec<Target>,
+) -> Vec<ReadOutcome> {
+    let mut reads = JoinSet::new();
+
+    for target in targets {
+        let in_flight = in_flight.clone();
+        let timeout = config.read_timeout(&target.sensor.id);
+        reads.spawn(async move {
+            let id = target.sensor.id.clone();
+            if !in_flight.sensors.lock().insert(id.clone()) {
+                eprintln!("Previous read of {} is still hanging, skipping", id);
+                return Ok(ReadOutcome::timed_out(target, None));
+            }
+
+            // Reads that hang hold on to their slot; give up rather than
+            // stall the poll when none becomes free.
+            let permits = in_flight.permits.clone();
+            let Ok(permit) = time::timeout(timeout, permits.acquire_owned()).await else {
+                eprintln!("No read of {} could start, all reads are hanging", id);
+                in_flight.sensors.lock().remove(&id);
+                return Ok(ReadOutcome::timed_out(target, None));
+            };
+            let permit = permit.unwrap();
+            let started = Instant::now();
+            let read = task::spawn_blocking({
+                let target = target.clone();
+                move || {
+                    // Held until the read returns, even if it was abandoned.
+                    let _permit = permit;
+                    let mut crc_failures = 0;
+                    let result = target.source.read(&target.sensor, &mut || {
+                        crc_failures += 1;
+                        eprintln!("CRC check failed for {}", id);
+                    });
+                    in_flight.sensors.lock().remove(&id);
+                    (result, crc_failures)
+                }
+            });
+
+            match time::timeout(timeout, read).await {
+                Ok(joined) => joined.map(|(result, crc_failures)| ReadOutcome {
+                    target,
+                    result,
+                    crc_failures,
+                    duration: Some(started.elapsed()),
+                }),
+                Err(_) => Ok(ReadOutcome::timed_out(target, Some(timeout))),
+            }
+        });
+    }
+
+    let mut outcomes = Vec::new();
+    while let Some(joined) = reads.join_next().await {
+        match joined {
+            Ok(Ok(outcome)) => outcomes.push(outcome),
+            Ok(Err(e)) | Err(e) => eprintln!("Sensor read task failed: {}", e),
+        }
+    }
+    // Reads finish in any order; keep the log output stable.
+    outcomes.sort_by(|a, b| a.target.sensor.id.cmp(&b.target.sensor.id));
+    outcomes
+}
+
+/// Updates the tracked sensors with the results of a poll and returns the
+/// IDs of the sensors that were read.
+fn apply_outcomes(
+    config: &Config,
+    state: &AppState,
+    tracked: &mut HashMap<String, TrackedSensor>,
+    outcomes: Vec<ReadOutcome>,
+    now: Instant,
+) -> HashSet<String> {
+    let mut read = HashSet::new();
+
+    for outcome in outcomes {
+        let Target { source, sensor } = outcome.target;
+        let sensor_name = sensor.id.clone();
+        read.insert(sensor_name.clone());
+        // Get or create the metrics for this sensor
+        let entry = tracked.entry(sensor_name.clone()).or_insert_with(|| {
+            println!("Discovered sensor {}", sensor_name);
+            TrackedSensor {
+                sensor: sensor.clone(),
+                metrics: SensorMetrics::new(
+                    &state.registry,
+                    &sensor,
+                    config.sensor_labels(&sensor.id),
+                ),
+                bus: source.bus_master(&sensor),
+                last_seen: now,
+                last_success: None,
+                present: true,
+            }
+        });
+        entry.last_seen = now;
+        entry.present = true;
+        let sensor_metrics = &mut entry.metrics;
+        sensor_metrics.set_present(true);
+        if let Some(duration) = outcome.duration {
+            sensor_metrics.observe_read_duration(duration);
+        }
+        sensor_metrics.inc_crc_failures(outcome.crc_failures);
+
+        // The exported temperature has the calibration offset applied; the
+        // sentinel checks compare raw readings.
+        let offset = config.sensor(&sensor_name).offset;
+        let previous = sensor_metrics.temperature().map(|temp| temp - offset);
+        let result = outcome
+            .result
+            .and_then(|measurement| source.check(measurement, previous));
+        match result {
+            Ok(measurement) => {
+                let temp = measurement.celsius + offset;
+                sensor_metrics.set_temperature(&state.registry, temp);
+                if let Some(humidity) = measurement.humidity {
+                    sensor_metrics.set_humidity(&state.registry, humidity);
+                }
+                if let Some(pressure) = measurement.pressure {
+                    sensor_metrics.set_pressure(&state.registry, pressure);
+                }
+                if let Some(bits) = measurement.resolution {
+                    sensor_metrics.set_resolution(&state.registry, bits);
+                }
+                entry.last_success = Some(now);
+                println!("Temperature for {}: {:.3}°C", sensor_name, temp);
+            }
+            Err(e) => {
+                sensor_metrics.inc_read_errors(e.kind());
+                if let ReadError::Sentinel(sentinel) = e {
+                    sensor_metrics.inc_invalid_readings(sentinel.reason());
+                }
+                eprintln!("Failed to read temperature from {}: {}", sensor_name, e)
+            }
+        }
+    }
+
+    read
+}
+
+/// Marks sensors that are no longer on the bus as absent and drops the
+/// metrics of sensors that stayed silent for longer than the grace period.
+/// Sensors listed in the configuration keep their presence metric, so their
+/// disappearance stays visible as `temperature_sensor_present == 0`.
+fn expire_sensors(
+    config: &Config,
+    state: &AppState,
+    tracked: &mut HashMap<String, TrackedSensor>,
+    present: &HashSet<String>,
+    now: Instant,
+) {
+    let mut removed = Vec::new();
+    for (id, sensor) in tracked.iter_mut() {
+        if present.contains(id) {
+            sensor.last_seen = now;
+        } else {
+            sensor.present = false;
+            sensor.metrics.set_present(false);
+        }
+
+        let last_success = sensor.last_success.unwrap_or(sensor.last_seen);
+        if now.duration_since(last_success) > config.grace_period {
+            sensor.metrics.clear_readings(&state.registry);
+        }
+
+        if now.duration_since(sensor.last_seen) > config.grace_period
+            && !config.sensors.contains_key(id)
+        {
+            removed.push(id.clone());
+        }
+    }
+
+    for id in removed {
+        if let Some(sensor) = tracked.remove(&id) {
+            println!("Sensor {} disappeared, removing its metrics", id);
+            sensor.metrics.unregister(&state.registry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn track(state: &AppState, id: &str, at: Instant) -> (String, TrackedSensor) {
+        let sensor = Sensor {
+            id: id.to_string(),
+            source: "w1",
+            family: "28".to_string(),
+            chip: "DS18B20".to_string(),
+            bus: None,
+        };
+        let mut metrics = SensorMetrics::new(&state.registry, &sensor, Default::default());
+        metrics.set_present(true);
+        metrics.set_temperature(&state.registry, 20.0);
+        let tracked = TrackedSensor {
+            sensor,
+            metrics,
+            bus: None,
+            last_seen: at,
+            last_success: Some(at),
+            present: true,
+        };
+        (id.to_string(), tracked)
+    }
+
+    /// The value of `temperature_sensor_present` for every registered sensor.
+    fn presence(state: &AppState) -> Vec<(String, f64)> {
+        let families = state.registry.gather();
+        let Some(family) = families
+            .iter()
+            .find(|family| family.get_name() == "temperature_sensor_present")
+        else {
+            return Vec::new();
+        };
+        let mut presence: Vec<(String, f64)> = family
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let sensor = metric
+                    .get_label()
+                    .iter()
+                    .find(|label| label.get_name() == "sensor")
+                    .unwrap();
+                (
+                    sensor.get_value().to_string(),
+                    metric.get_gauge().get_value(),
+                )
+            })
+            .collect();
+        presence.sort_by(|a, b| a.0.cmp(&b.0));
+        presence
+    }
+
+    #[test]
+    fn expires_sensors_after_the_grace_period() {
+        let state = AppState {
+            registry: Arc::new(Registry::new()),
+            sensors: Arc::default(),
+        };
+        let mut config = Config {
+            grace_period: Duration::from_secs(300),
+            ..Default::default()
+        };
+        config
+            .sensors
+            .insert("28-listed".to_string(), Default::default());
+        let start = Instant::now();
+        let mut tracked: HashMap<String, TrackedSensor> =
+            ["28-ok", "28-failing", "28-gone", "28-listed"]
+                .into_iter()
+                .map(|id| track(&state, id, start))
+                .collect();
+        let present: HashSet<String> = ["28-ok", "28-failing"].map(String::from).into();
+
+        // Within the grace period the missing sensors are only marked absent.
+        let now = start + Duration::from_secs(100);
+        tracked.get_mut("28-ok").unwrap().last_success = Some(now);
+        expire_sensors(&config, &state, &mut tracked, &present, now);
+        assert_eq!(tracked.len(), 4);
+        assert!(!tracked["28-gone"].present);
+        assert_eq!(tracked["28-gone"].metrics.temperature(), Some(20.0));
+        assert_eq!(
+            presence(&state),
+            [
+                ("28-failing".to_string(), 1.0),
+                ("28-gone".to_string(), 0.0),
+                ("28-listed".to_string(), 0.0),
+                ("28-ok".to_string(), 1.0),
+            ]
+        );
+
+        let now = start + Duration::from_secs(301);
+        tracked.get_mut("28-ok").unwrap().last_success = Some(now);
+        expire_sensors(&config, &state, &mut tracked, &present, now);
+        // Readings of sensors without a recent success are dropped, and
+        // sensors missing for too long are forgotten unless configured.
+        assert!(!tracked.contains_key("28-gone"));
+        assert_eq!(tracked["28-ok"].metrics.temperature(), Some(20.0));
+        assert_eq!(tracked["28-failing"].metrics.temperature(), None);
+        assert!(tracked["28-failing"].present);
+        assert_eq!(tracked["28-listed"].metrics.temperature(), None);
+        assert_eq!(
+            presence(&state),
+            [
+                ("28-failing".to_string(), 1.0),
+                ("28-listed".to_string(), 0.0),
+                ("28-ok".to_string(), 1.0),
+            ]
+        );
+    }
+
+    /// A source whose reads of `hang` block until `release` is dropped.
+    struct HangingSource {
+        release: Mutex<Option<std::sync::mpsc::Receiver<()>>>,
+    }
+
+    impl SensorSource for HangingSource {
+        fn name(&self) -> &str {
+            "hanging"
+        }
+
+        fn discover(&self) -> std::io::Result<Vec<Sensor>> {
+            Ok(Vec::new())
+        }
+
+        fn read(
+            &self,
+            sensor: &Sensor,
+            _on_crc_failure: &mut dyn FnMut(),
+        ) -> Result<Measurement, ReadError> {
+            if sensor.id == "hang" {
+                let release = self.release.lock().take().unwrap();
+                let _ = release.recv();
+            }
+            Ok(Measurement {
+                celsius: 20.0,
+                resolution: None,
+                humidity: None,
+                pressure: None,
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hanging_reads_keep_their_slot() {
+        let (release, hang) = std::sync::mpsc::channel();
+        let source: Arc<dyn SensorSource> = Arc::new(HangingSource {
+            release: Mutex::new(Some(hang)),
+        });
+        let target = |id: &str| Target {
+            source: source.clone(),
+            sensor: Sensor {
+                id: id.to_string(),
+                source: "hanging",
+                family: String::new(),
+                chip: String::new(),
+                bus: None,
+            },
+        };
+        let mut config = Config::default();
+        config.w1.max_concurrent_reads = 1;
+        config.w1.read_timeout = Duration::from_millis(50);
+        let in_flight = InFlight::new(&config);
+
+        let outcomes = read_sensors(&config, &in_flight, vec![target("hang")]).await;
+        assert!(matches!(outcomes[0].result, Err(ReadError::Timeout)));
+        assert_eq!(outcomes[0].duration, Some(config.w1.read_timeout));
+
+        // The abandoned read still occupies the only slot.
+        let outcomes = read_sensors(&config, &in_flight, vec![target("ok")]).await;
+        assert!(matches!(outcomes[0].result, Err(ReadError::Timeout)));
+        assert_eq!(outcomes[0].duration, None);
+
+        drop(release);
+        while !in_flight.sensors.lock().is_empty() {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+        let outcomes = read_sensors(&config, &in_flight, vec![target("ok")]).await;
+        assert!(outcomes[0].result.is_ok());
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// When each poll interval is due next. Ticks fall on multiples of their
+/// interval on the wall clock, so the period does not drift by the time a
+/// poll takes. A tick missed while a long poll was running is made up once
+/// right after it, not once per missed tick.
+pub struct Schedule {
+    next: BTreeMap<Duration, SystemTime>,
+}
+
+impl Schedule {
+    /// A schedule on which every interval is due right away.
+    pub fn new(intervals: BTreeSet<Duration>) -> Self {
+        let now = SystemTime::now();
+        Self {
+            next: intervals
+                .into_iter()
+                .map(|interval| (interval, now))
+                .collect(),
+        }
+    }
+
+    /// Returns the intervals that are due at `now` and moves each of them to
+    /// its next tick.
+    pub fn take_due(&mut self, now: SystemTime) -> BTreeSet<Duration> {
+        let mut due = BTreeSet::new();
+        for (interval, next) in &mut self.next {
+            if *next <= now {
+                due.insert(*interval);
+                *next = next_tick(*interval, now);
+            }
+        }
+        due
+    }
+
+    /// How long until the next interval is due.
+    pub fn until_next(&self) -> Duration {
+        let now = SystemTime::now();
+        self.next.values().min().map_or(Duration::ZERO, |next| {
+            next.duration_since(now).unwrap_or_default()
+        })
+    }
+}
+
+/// The first multiple of `interval` since the Unix epoch that lies after `now`.
+fn next_tick(interval: Duration, now: SystemTime) -> SystemTime {
+    let elapsed = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let interval = interval.as_nanos();
+    let tick = (elapsed / interval + 1) * interval;
+    UNIX_EPOCH + Duration::from_nanos(tick as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+    const TEN_SECONDS: Duration = Duration::from_secs(10);
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn ticks_fall_on_wall_clock_multiples() {
+        assert_eq!(next_tick(MINUTE, at(125)), at(180));
+        assert_eq!(next_tick(MINUTE, at(120)), at(180));
+        assert_eq!(
+            next_tick(MINUTE, at(179) + Duration::from_millis(999)),
+            at(180)
+        );
+        assert_eq!(next_tick(TEN_SECONDS, at(125)), at(130));
+    }
+
+    #[test]
+    fn takes_due_intervals() {
+        let mut schedule = Schedule::new([MINUTE, TEN_SECONDS].into());
+        // A minute boundary after the schedule was created.
+        let start = next_tick(MINUTE, SystemTime::now());
+        let after = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(schedule.take_due(start), [TEN_SECONDS, MINUTE].into());
+        assert!(schedule.take_due(after(5)).is_empty());
+        assert_eq!(schedule.take_due(after(10)), [TEN_SECONDS].into());
+        assert_eq!(schedule.take_due(after(60)), [TEN_SECONDS, MINUTE].into());
+    }
+
+    #[test]
+    fn makes_up_missed_ticks_once() {
+        let mut schedule = Schedule::new([TEN_SECONDS].into());
+        let start = next_tick(MINUTE, SystemTime::now());
+        let after = |secs| start + Duration::from_secs(secs);
+
+        schedule.take_due(start);
+        // A poll that ran for 45s missed four ticks.
+        assert_eq!(schedule.take_due(after(45)), [TEN_SECONDS].into());
+        assert!(schedule.take_due(after(49)).is_empty());
+        assert_eq!(schedule.take_due(after(50)), [TEN_SECONDS].into());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::{
+    config::Config,
+    metrics,
+    poller::{self, InFlight},
+    source::{SensorSource, Target},
+};
+use parking_lot::Mutex;
+use prometheus::{
+    core::{Collector, Desc},
+    proto::{self, LabelPair, MetricFamily, MetricType},
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+use tokio::runtime::Handle;
+
+const TEMPERATURE: (&str, &str) = (
+    "temperature_celsius",
+    "Temperature reading in degrees Celsius",
+);
+const HUMIDITY: (&str, &str) = ("humidity_percent", "Relative humidity in percent");
+const PRESSURE: (&str, &str) = ("pressure_pascals", "Air pressure in pascals");
+const PRESENT: (&str, &str) = (
+    "temperature_sensor_present",
+    "Whether the sensor was found on the bus during the last read",
+);
+const LAST_SUCCESS: (&str, &str) = (
+    "temperature_sensor_last_success_timestamp_seconds",
+    "Unix time of the last successful reading",
+);
+const SENSORS_PRESENT: (&str, &str) = (
+    "temperature_sensors_present",
+    "Number of polled sensors found on the bus during the last read",
+);
+
+/// A sensor as of the last read.
+struct CachedSensor {
+    /// Name of the source the sensor was discovered by.
+    source: String,
+    labels: BTreeMap<String, String>,
+    present: bool,
+    /// The last accepted reading, with the offset applied.
+    temperature: Option<f64>,
+    /// Humidity and pressure of the last accepted reading, for devices that
+    /// measure them.
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+    last_success: Option<SystemTime>,
+}
+
+#[derive(Default)]
+struct Cache {
+    read_at: Option<Instant>,
+    sensors: BTreeMap<String, CachedSensor>,
+}
+
+/// Reads the sensors while the registry is gathered, for `scrape.enabled`.
+/// Results are kept for `scrape.cache_ttl`; scrapes arriving while a read
+/// is running wait for it and share its results.
+///
+/// `collect` blocks on the reads, so the registry must be gathered outside
+/// of the async runtime, e.g. with `spawn_blocking`.
+pub struct ScrapeCollector {
+    config: Arc<Config>,
+    sources: Vec<Arc<dyn SensorSource>>,
+    runtime: Handle,
+    in_flight: InFlight,
+    descs: Vec<Desc>,
+    cache: Mutex<Cache>,
+}
+
+impl ScrapeCollector {
+    /// Creates the collector. Must be called from within the runtime the
+    /// reads should run on.
+    pub fn new(config: Arc<Config>, sources: Vec<Arc<dyn SensorSource>>) -> Self {
+        let descs = [
+            TEMPERATURE,
+            HUMIDITY,
+            PRESSURE,
+            PRESENT,
+            LAST_SUCCESS,
+            SENSORS_PRESENT,
+        ]
+        .into_iter()
+        .map(|(name, help)| {
+            Desc::new(name.to_string(), help.to_string(), vec![], HashMap::new()).unwrap()
+        })
+        .collect();
+        Self {
+            in_flight: InFlight::new(&config),
+            config,
+            sources,
+            runtime: Handle::current(),
+            descs,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Reads the sensors of every source into `cache`.
+    fn refresh(&self, cache: &mut Cache) {
+        let config = &*self.config;
+        let mut targets = Vec::new();
+        // Sensors of sources that could not be listed keep their state.
+        let mut failed = HashSet::new();
+        for source in &self.sources {
+            match source.discover() {
+                Ok(sensors) => targets.extend(sensors.into_iter().map(|sensor| Target {
+                    source: source.clone(),
+                    sensor,
+                })),
+                Err(e) => {
+                    eprintln!("Failed to discover {} sensors: {}", source.name(), e);
+                    failed.insert(source.name().to_string());
+                }
+            }
+        }
+
+        for sensor in cache.sensors.values_mut() {
+            if !failed.contains(&sensor.source) {
+                sensor.present = false;
+            }
+        }
+        let outcomes = self.runtime.block_on(async {
+            poller::prepare_sources(&targets).await;
+            poller::read_sensors(config, &self.in_flight, targets).await
+        });
+        let now = SystemTime::now();
+        for outcome in outcomes {
+            let Target { source, sensor } = outcome.target;
+            let id = sensor.id.clone();
+            let cached = cache.sensors.entry(id.clone()).or_insert_with(|| {
+                println!("Discovered sensor {}", id);
+                let mut labels: BTreeMap<String, String> =
+                    metrics::sensor_labels(&sensor).into_iter().collect();
+                labels.extend(config.sensor_labels(&id));
+                CachedSensor {
+                    source: source.name().to_string(),
+                    labels,
+                    present: true,
+                    temperature: None,
+                    humidity: None,
+                    pressure: None,
+                    last_success: None,
+                }
+            });
+            cached.present = true;
+
+            let offset = config.sensor(&id).offset;
+            let previous = cached.temperature.map(|temp| temp - offset);
+            let result = outcome
+                .result
+                .and_then(|measurement| source.check(measurement, previous));
+            match result {
+                Ok(measurement) => {
+                    let temp = measurement.celsius + offset;
+                    cached.temperature = Some(temp);
+                    // Keep the previous value of a quantity the read did not
+                    // return; it expires with the temperature.
+                    cached.humidity = measurement.humidity.or(cached.humidity);
+                    cached.pressure = measurement.pressure.or(cached.pressure);
+                    cached.last_success = Some(now);
+                }
+                Err(e) => eprintln!("Failed to read temperature from {}: {}", id, e),
+            }
+        }
+
+        // Like the background poller, keep a stale value for the grace period
+        // and keep sensors listed in the configuration as absent.
+        cache.sensors.retain(|id, sensor| {
+            let stale = sensor.last_success.is_none_or(|success| {
+                now.duration_since(success).unwrap_or_default() > config.grace_period
+            });
+            if stale {
+                sensor.temperature = None;
+                sensor.humidity = None;
+                sensor.pressure = None;
+            }
+            sensor.present || failed.contains(&sensor.source) || config.sensors.contains_key(id)
+        });
+        cache.read_at = Some(Instant::now());
+    }
+}
+
+impl Collector for ScrapeCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut cache = self.cache.lock();
+        if cache
+            .read_at
+            .is_none_or(|read_at| read_at.elapsed() >= self.config.scrape.cache_ttl)
+        {
+            self.refresh(&mut cache);
+        }
+
+        let sensors = cache.sensors.values();
+        let present = sensors.clone().filter(|sensor| sensor.present).count();
+        vec![
+            gauge_family(
+                TEMPERATURE,
+                sensors.clone().filter_map(|sensor| {
+                    sensor.temperature.map(|temp| metric(&sensor.labels, temp))
+                }),
+            ),
+            gauge_family(
+                HUMIDITY,
+                sensors.clone().filter_map(|sensor| {
+                    sensor
+                        .humidity
+                        .map(|humidity| metric(&sensor.labels, humidity))
+                }),
+            ),
+            gauge_family(
+                PRESSURE,
+                sensors.clone().filter_map(|sensor| {
+                    sensor
+                        .pressure
+                        .map(|pressure| metric(&sensor.labels, pressure))
+                }),
+            ),
+            gauge_family(
+                PRESENT,
+                sensors
+                    .clone()
+                    .map(|sensor| metric(&sensor.labels, if sensor.present { 1.0 } else { 0.0 })),
+            ),
+            gauge_family(
+                LAST_SUCCESS,
+                sensors.filter_map(|sensor| {
+                    let success = sensor.last_success?;
+                    let since_epoch = success.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+                    Some(metric(&sensor.labels, since_epoch.as_secs_f64()))
+                }),
+            ),
+            gauge_family(SENSORS_PRESENT, [metric(&BTreeMap::new(), present as f64)]),
+        ]
+    }
+}
+
+fn gauge_family(
+    (name, help): (&str, &str),
+    metrics: impl IntoIterator<Item = proto::Metric>,
+) -> MetricFamily {
+    let mut family = MetricFamily::default();
+    family.set_name(name.to_string());
+    family.set_help(help.to_string());
+    family.set_field_type(MetricType::GAUGE);
+    family.mut_metric().extend(metrics);
+    family
+}
+
+fn metric(labels: &BTreeMap<String, String>, value: f64) -> proto::Metric {
+    let mut gauge = proto::Gauge::default();
+    gauge.set_value(value);
+    let mut metric = proto::Metric::default();
+    for (name, value) in labels {
+        let mut label = LabelPair::default();
+        label.set_name(name.clone());
+        label.set_value(value.clone());
+        metric.mut_label().push(label);
+    }
+    metric.set_gauge(gauge);
+    metric
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{Measurement, ReadError, Sensor};
+    use std::{io, time::Duration};
+
+    /// A source whose sensors and readings the test controls.
+    struct FakeSource {
+        /// The sensors listed, or `None` to fail listing.
+        sensors: Mutex<Option<Vec<&'static str>>>,
+        /// The reading of every sensor, or `None` to fail reading.
+        celsius: Mutex<Option<f64>>,
+        reads: Mutex<usize>,
+    }
+
+    impl FakeSource {
+        fn new(sensors: &[&'static str]) -> Arc<Self> {
+            Arc::new(Self {
+                sensors: Mutex::new(Some(sensors.to_vec())),
+                celsius: Mutex::new(Some(21.5)),
+                reads: Mutex::new(0),
+            })
+        }
+    }
+
+    impl SensorSource for FakeSource {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn discover(&self) -> io::Result<Vec<Sensor>> {
+            let sensors = self.sensors.lock().clone().ok_or(io::ErrorKind::Other)?;
+            Ok(sensors
+                .into_iter()
+                .map(|id| Sensor {
+                    id: id.to_string(),
+                    source: "fake",
+                    family: String::new(),
+                    chip: String::new(),
+                    bus: None,
+                })
+                .collect())
+        }
+
+        fn read(
+            &self,
+            _sensor: &Sensor,
+            _on_crc_failure: &mut dyn FnMut(),
+        ) -> Result<Measurement, ReadError> {
+            *self.reads.lock() += 1;
+            let celsius = self.celsius.lock().ok_or(ReadError::Timeout)?;
+            Ok(Measurement {
+                celsius,
+                resolution: None,
+                humidity: None,
+                pressure: None,
+            })
+        }
+    }
+
+    /// The value of the series of `family` for sensor `id`.
+    fn value(families: &[MetricFamily], family: &str, id: &str) -> Option<f64> {
+        families
+            .iter()
+            .find(|f| f.get_name() == family)?
+            .get_metric()
+            .iter()
+            .find(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "sensor" && label.get_value() == id)
+            })
+            .map(|metric| metric.get_gauge().get_value())
+    }
+
+    fn collector(config: Config, source: &Arc<FakeSource>) -> ScrapeCollector {
+        let source: Arc<dyn SensorSource> = source.clone();
+        ScrapeCollector::new(Arc::new(config), vec![source])
+    }
+
+    #[test]
+    fn reuses_readings_within_the_cache_ttl() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let source = FakeSource::new(&["a"]);
+        let collector = collector(Config::default(), &source);
+
+        assert_eq!(value(&collector.collect(), TEMPERATURE.0, "a"), Some(21.5));
+        *source.celsius.lock() = Some(22.0);
+        assert_eq!(value(&collector.collect(), TEMPERATURE.0, "a"), Some(21.5));
+        assert_eq!(*source.reads.lock(), 1);
+    }
+
+    #[test]
+    fn expires_stale_readings_and_missing_sensors() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let source = FakeSource::new(&["a", "b", "c"]);
+        let mut config = Config::default();
+        config.scrape.cache_ttl = Duration::ZERO;
+        config.grace_period = Duration::ZERO;
+        config.sensors.insert("b".to_string(), Default::default());
+        let collector = collector(config, &source);
+        collector.collect();
+
+        // `a` fails to read, `b` and `c` are gone.
+        *source.sensors.lock() = Some(vec!["a"]);
+        *source.celsius.lock() = None;
+        std::thread::sleep(Duration::from_millis(1));
+        let families = collector.collect();
+        assert_eq!(value(&families, TEMPERATURE.0, "a"), None);
+        assert_eq!(value(&families, PRESENT.0, "a"), Some(1.0));
+        // Sensors listed in the configuration stay, marked absent.
+        assert_eq!(value(&families, PRESENT.0, "b"), Some(0.0));
+        assert_eq!(value(&families, PRESENT.0, "c"), None);
+    }
+
+    #[test]
+    fn keeps_sensors_of_sources_that_failed_to_list() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let source = FakeSource::new(&["a"]);
+        let mut config = Config::default();
+        config.scrape.cache_ttl = Duration::ZERO;
+        let collector = collector(config, &source);
+        collector.collect();
+
+        *source.sensors.lock() = None;
+        let families = collector.collect();
+        assert_eq!(value(&families, PRESENT.0, "a"), Some(1.0));
+        assert_eq!(value(&families, TEMPERATURE.0, "a"), Some(21.5));
+        assert_eq!(*source.reads.lock(), 1);
+    }
+}
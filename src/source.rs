@@ -0,0 +1,214 @@
+mod hwmon;
+mod iio;
+mod owserver;
+mod thermal;
+mod w1;
+
+pub use self::{
+    hwmon::HwmonSource, iio::IioSource, owserver::OwserverSource, thermal::ThermalSource,
+    w1::W1Source,
+};
+
+use crate::{config::Config, hotplug};
+use std::{error::Error, fmt, fs, io, path::Path, sync::Arc, time::Duration};
+
+/// Why reading a sensor failed.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The device files could not be read.
+    Io(io::Error),
+    /// The device returned data we could not make sense of.
+    Parse(String),
+    /// The driver reported a CRC mismatch for the scratchpad.
+    Crc,
+    /// The sensor returned one of the DS18B20 sentinel values.
+    Sentinel(crate::w1::Sentinel),
+    /// The read did not finish within the configured timeout.
+    Timeout,
+}
+
+impl ReadError {
+    /// Value of the `kind` label of the read error counter.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReadError::Io(_) => "io",
+            ReadError::Parse(_) => "parse",
+            ReadError::Crc => "crc",
+            ReadError::Sentinel(_) => "sentinel",
+            ReadError::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "{}", e),
+            ReadError::Parse(msg) => write!(f, "{}", msg),
+            ReadError::Crc => write!(f, "CRC check failed"),
+            ReadError::Sentinel(s) => write!(f, "sentinel value ({})", s.reason()),
+            ReadError::Timeout => write!(f, "read timed out"),
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// A successful reading of a sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub celsius: f64,
+    /// The conversion resolution in bits, when it could be decoded from the
+    /// scratchpad.
+    pub resolution: Option<u8>,
+    /// Relative humidity in percent, for devices that measure it.
+    pub humidity: Option<f64>,
+    /// Air pressure in pascals, for devices that measure it.
+    pub pressure: Option<f64>,
+}
+
+/// A sensor as reported by its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sensor {
+    /// Unique ID of the sensor, also the key of its entry under `sensors`.
+    pub id: String,
+    /// Kind of source the sensor was found by, exported as the `source`
+    /// label: `w1`, `owserver`, `hwmon`, `thermal` or `iio`.
+    pub source: &'static str,
+    /// Family code of the device, exported as the `family` label.
+    pub family: String,
+    /// Chip name, exported as the `chip` label.
+    pub chip: String,
+    /// The 1-Wire bus the sensor hangs off: the w1 bus master or the
+    /// owserver address. `None` for sources without one, such as hwmon.
+    pub bus: Option<String>,
+}
+
+impl Sensor {
+    /// Name of the bus, or `unknown` if there is none.
+    pub fn bus_name(&self) -> &str {
+        self.bus.as_deref().unwrap_or("unknown")
+    }
+}
+
+/// Details about a device that are not part of its readings.
+#[derive(Debug, Default)]
+pub struct Metadata {
+    /// Conversion resolution in bits.
+    pub resolution: Option<u8>,
+    /// Whether the device is externally powered rather than parasite powered.
+    pub external_power: Option<bool>,
+}
+
+/// Somewhere sensors can be discovered and read. Everything except `watch`
+/// may block and is called on the blocking pool.
+pub trait SensorSource: Send + Sync {
+    /// Name of the source, used in log messages.
+    fn name(&self) -> &str;
+
+    /// Lists the sensors that should be read.
+    fn discover(&self) -> io::Result<Vec<Sensor>>;
+
+    /// Reads `sensor`. `on_crc_failure` is called for every attempt that was
+    /// rejected by a CRC check.
+    fn read(
+        &self,
+        sensor: &Sensor,
+        on_crc_failure: &mut dyn FnMut(),
+    ) -> Result<Measurement, ReadError>;
+
+    /// Rejects a reading that is not a real measurement. `previous` is the
+    /// last accepted reading of the same sensor, if any, without the
+    /// calibration offset.
+    fn check(
+        &self,
+        measurement: Measurement,
+        _previous: Option<f64>,
+    ) -> Result<Measurement, ReadError> {
+        Ok(measurement)
+    }
+
+    /// Prepares a newly discovered sensor for its first read.
+    fn setup(&self, _sensor: &Sensor) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Reads the details of `sensor`.
+    fn metadata(&self, _sensor: &Sensor) -> io::Result<Metadata> {
+        Ok(Metadata::default())
+    }
+
+    /// How often `metadata` is read again after a sensor was discovered, or
+    /// `None` to read it only once.
+    fn metadata_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The w1 bus master `sensor` hangs off, for sources whose buses the bus
+    /// supervisor can recover.
+    fn bus_master(&self, _sensor: &Sensor) -> Option<String> {
+        None
+    }
+
+    /// Called before every poll that reads sensors of this source.
+    fn prepare(&self) {}
+
+    /// Starts watching for sensors being attached and detached, if the
+    /// source supports it and it is enabled.
+    fn watch(&self) -> io::Result<Option<hotplug::Watcher>> {
+        Ok(None)
+    }
+}
+
+/// A sensor together with the source it is read from.
+#[derive(Clone)]
+pub struct Target {
+    pub source: Arc<dyn SensorSource>,
+    pub sensor: Sensor,
+}
+
+/// The sources enabled in `config`.
+pub fn from_config(config: &Arc<Config>) -> Vec<Arc<dyn SensorSource>> {
+    let mut sources: Vec<Arc<dyn SensorSource>> = Vec::new();
+    if config.w1.enabled {
+        sources.push(Arc::new(W1Source::new(config.clone())));
+    }
+    for server in &config.owserver {
+        sources.push(Arc::new(OwserverSource::new(config.clone(), server)));
+    }
+    if config.hwmon.enabled {
+        sources.push(Arc::new(HwmonSource::new(config.clone())));
+    }
+    if config.thermal.enabled {
+        sources.push(Arc::new(ThermalSource::new(config.clone())));
+    }
+    if config.iio.enabled {
+        sources.push(Arc::new(IioSource::new(config.clone())));
+    }
+    sources
+}
+
+/// Reads a sysfs attribute holding a temperature in millidegrees Celsius.
+fn read_millidegrees(path: &Path) -> Result<Measurement, ReadError> {
+    let content = fs::read_to_string(path)?;
+    let millidegrees: i64 = content.trim().parse().map_err(|e| {
+        ReadError::Parse(format!("Invalid temperature {:?}: {}", content.trim(), e))
+    })?;
+    Ok(Measurement {
+        celsius: millidegrees as f64 / 1000.0,
+        resolution: None,
+        humidity: None,
+        pressure: None,
+    })
+}
+
+/// Reads a one-line sysfs attribute such as `name`, without the newline.
+fn read_name(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
@@ -0,0 +1,129 @@
+use super::{read_millidegrees, read_name, Measurement, ReadError, Sensor, SensorSource};
+use crate::config::Config;
+use std::{fs, io, path::Path, sync::Arc};
+
+/// Temperature channels of the kernel's hardware monitoring drivers, under
+/// `/sys/class/hwmon/hwmon*/temp*_input`.
+///
+/// Sensors are identified as `hwmon0/temp1`. The driver name from the
+/// device's `name` file is exported as the `chip` label.
+pub struct HwmonSource {
+    config: Arc<Config>,
+}
+
+impl HwmonSource {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+
+    /// The temperature channels of the device directory `path`.
+    fn channels(&self, device: &str, path: &Path) -> io::Result<Vec<Sensor>> {
+        let chip = read_name(&path.join("name")).unwrap_or_else(|_| "unknown".to_string());
+        let mut sensors = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let file = entry?.file_name();
+            let Some(channel) = file
+                .to_str()
+                .and_then(|name| name.strip_suffix("_input"))
+                .filter(|channel| channel.starts_with("temp"))
+            else {
+                continue;
+            };
+            sensors.push(Sensor {
+                id: format!("{}/{}", device, channel),
+                source: "hwmon",
+                family: String::new(),
+                chip: chip.clone(),
+                bus: None,
+            });
+        }
+        Ok(sensors)
+    }
+}
+
+impl SensorSource for HwmonSource {
+    fn name(&self) -> &str {
+        "hwmon"
+    }
+
+    fn discover(&self) -> io::Result<Vec<Sensor>> {
+        let mut sensors = Vec::new();
+        for entry in fs::read_dir(&self.config.hwmon.path)? {
+            let entry = entry?;
+            let Some(device) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !device.starts_with("hwmon") {
+                continue;
+            }
+            match self.channels(&device, &entry.path()) {
+                Ok(channels) => sensors.extend(channels),
+                Err(e) => eprintln!("Failed to list channels of {}: {}", device, e),
+            }
+        }
+        sensors.retain(|sensor| self.config.sensor(&sensor.id).enabled);
+        sensors.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(sensors)
+    }
+
+    fn read(
+        &self,
+        sensor: &Sensor,
+        _on_crc_failure: &mut dyn FnMut(),
+    ) -> Result<Measurement, ReadError> {
+        read_millidegrees(&self.config.hwmon.path.join(format!("{}_input", sensor.id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SensorConfig;
+    use std::process;
+
+    #[test]
+    fn discovers_temperature_channels() {
+        let path = std::env::temp_dir().join(format!("temperatures-{}-hwmon", process::id()));
+        let files = [
+            ("hwmon0/name", "cpu_thermal\n"),
+            ("hwmon0/temp1_input", "48312\n"),
+            ("hwmon1/name", "nvme\n"),
+            ("hwmon1/temp1_input", "35850\n"),
+            ("hwmon1/temp1_crit", "84850\n"),
+            ("hwmon1/temp2_input", "37850\n"),
+            ("hwmon1/in0_input", "1200\n"),
+            ("hwmon2/temp1_input", "40000\n"),
+        ];
+        for (file, content) in files {
+            fs::create_dir_all(path.join(file).parent().unwrap()).unwrap();
+            fs::write(path.join(file), content).unwrap();
+        }
+        let mut config = Config::default();
+        config.hwmon.path = path.clone();
+        config.sensors.insert(
+            "hwmon1/temp2".to_string(),
+            SensorConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        );
+        let source = HwmonSource::new(Arc::new(config));
+        let sensors = source.discover().unwrap();
+        let reading = source.read(&sensors[1], &mut || {});
+        fs::remove_dir_all(&path).unwrap();
+
+        let sensors: Vec<(&str, &str)> = sensors
+            .iter()
+            .map(|sensor| (sensor.id.as_str(), sensor.chip.as_str()))
+            .collect();
+        assert_eq!(
+            sensors,
+            [
+                ("hwmon0/temp1", "cpu_thermal"),
+                ("hwmon1/temp1", "nvme"),
+                ("hwmon2/temp1", "unknown"),
+            ]
+        );
+        assert_eq!(reading.unwrap().celsius, 35.85);
+    }
+}
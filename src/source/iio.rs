@@ -0,0 +1,265 @@
+use super::{read_name, Measurement, ReadError, Sensor, SensorSource};
+use crate::config::Config;
+use std::{fs, io, path::Path, sync::Arc};
+
+/// Environmental sensors of the kernel's Industrial I/O drivers, such as
+/// the BME280 or DHT22, under `/sys/bus/iio/devices/iio:device*`.
+///
+/// Devices with a temperature channel are read; humidity and pressure are
+/// read along with it where the device has them. Sensors are identified by
+/// their device directory, e.g. `iio:device0`, and the driver's `name` file
+/// is exported as the `chip` label.
+pub struct IioSource {
+    config: Arc<Config>,
+}
+
+impl IioSource {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+}
+
+/// Reads the IIO channel `in_<channel>` of the device at `path`, in the
+/// channel's ABI units, or `None` if the device has no such channel.
+///
+/// The processed `_input` attribute is used where the driver provides one.
+/// Otherwise the value is computed from the `_raw` attribute as
+/// `(raw + offset) * scale`, with `_offset` and `_scale` defaulting to 0
+/// and 1.
+fn channel(path: &Path, channel: &str) -> Result<Option<f64>, ReadError> {
+    let attribute = |suffix: &str| -> Result<Option<f64>, ReadError> {
+        let content = match fs::read_to_string(path.join(format!("in_{}_{}", channel, suffix))) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        content.trim().parse().map(Some).map_err(|e| {
+            ReadError::Parse(format!(
+                "Invalid in_{}_{} {:?}: {}",
+                channel,
+                suffix,
+                content.trim(),
+                e
+            ))
+        })
+    };
+
+    if let Some(value) = attribute("input")? {
+        return Ok(Some(value));
+    }
+    let Some(raw) = attribute("raw")? else {
+        return Ok(None);
+    };
+    let offset = attribute("offset")?.unwrap_or(0.0);
+    let scale = attribute("scale")?.unwrap_or(1.0);
+    Ok(Some((raw + offset) * scale))
+}
+
+fn has_channel(path: &Path, channel: &str) -> bool {
+    ["input", "raw"]
+        .iter()
+        .any(|suffix| path.join(format!("in_{}_{}", channel, suffix)).exists())
+}
+
+impl SensorSource for IioSource {
+    fn name(&self) -> &str {
+        "iio"
+    }
+
+    fn discover(&self) -> io::Result<Vec<Sensor>> {
+        let mut sensors = Vec::new();
+        for entry in fs::read_dir(&self.config.iio.devices_path)? {
+            let entry = entry?;
+            let Some(device) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !device.starts_with("iio:device") || !has_channel(&entry.path(), "temp") {
+                continue;
+            }
+            sensors.push(Sensor {
+                chip: read_name(&entry.path().join("name"))
+                    .unwrap_or_else(|_| "unknown".to_string()),
+                id: device,
+                source: "iio",
+                family: String::new(),
+                bus: None,
+            });
+        }
+        sensors.retain(|sensor| self.config.sensor(&sensor.id).enabled);
+        sensors.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(sensors)
+    }
+
+    fn read(
+        &self,
+        sensor: &Sensor,
+        _on_crc_failure: &mut dyn FnMut(),
+    ) -> Result<Measurement, ReadError> {
+        let path = self.config.iio.devices_path.join(&sensor.id);
+        // The IIO ABI reports temperatures in millidegrees Celsius, relative
+        // humidity in millipercent and pressure in kilopascals.
+        let millidegrees = channel(&path, "temp")?.ok_or_else(|| {
+            ReadError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no temperature channel",
+            ))
+        })?;
+        Ok(Measurement {
+            celsius: millidegrees / 1000.0,
+            resolution: None,
+            humidity: channel(&path, "humidityrelative")?.map(|milli| milli / 1000.0),
+            pressure: channel(&path, "pressure")?.map(|kilopascals| kilopascals * 1000.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SensorConfig;
+    use std::{path::PathBuf, process};
+
+    /// A devices directory under the system temp dir holding `devices`,
+    /// each a device name and its attribute files.
+    fn devices(name: &str, devices: &[(&str, &[(&str, &str)])]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("temperatures-{}-{}", process::id(), name));
+        for (device, files) in devices {
+            fs::create_dir_all(path.join(device)).unwrap();
+            for (file, content) in *files {
+                fs::write(path.join(device).join(file), content).unwrap();
+            }
+        }
+        path
+    }
+
+    fn source(devices_path: &Path, mut config: Config) -> IioSource {
+        config.iio.devices_path = devices_path.to_path_buf();
+        IioSource::new(Arc::new(config))
+    }
+
+    fn sensor(id: &str) -> Sensor {
+        Sensor {
+            id: id.to_string(),
+            source: "iio",
+            family: String::new(),
+            chip: String::new(),
+            bus: None,
+        }
+    }
+
+    #[test]
+    fn computes_channels() {
+        let path = devices(
+            "iio-channels",
+            &[(
+                "iio:device0",
+                &[
+                    ("in_temp_input", "21500\n"),
+                    ("in_temp_raw", "1\n"),
+                    ("in_pressure_raw", "100\n"),
+                    ("in_pressure_offset", "-50\n"),
+                    ("in_pressure_scale", "0.5\n"),
+                    ("in_humidityrelative_raw", "42\n"),
+                    ("in_voltage0_raw", "garbage\n"),
+                ],
+            )],
+        );
+        let device = path.join("iio:device0");
+        let values = [
+            channel(&device, "temp"),
+            channel(&device, "pressure"),
+            channel(&device, "humidityrelative"),
+            channel(&device, "current"),
+        ];
+        let invalid = channel(&device, "voltage0");
+        fs::remove_dir_all(&path).unwrap();
+
+        let values: Vec<Option<f64>> = values.into_iter().map(Result::unwrap).collect();
+        // The processed value wins over the raw one.
+        assert_eq!(values[0], Some(21500.0));
+        assert_eq!(values[1], Some(25.0));
+        // Offset and scale default to 0 and 1.
+        assert_eq!(values[2], Some(42.0));
+        assert_eq!(values[3], None);
+        assert!(matches!(invalid, Err(ReadError::Parse(_))));
+    }
+
+    #[test]
+    fn discovers_devices_with_a_temperature_channel() {
+        let path = devices(
+            "iio-discover",
+            &[
+                (
+                    "iio:device0",
+                    &[("name", "bme280\n"), ("in_temp_input", "21500\n")],
+                ),
+                (
+                    "iio:device1",
+                    &[("name", "ads1015\n"), ("in_voltage0_raw", "1\n")],
+                ),
+                ("iio:device2", &[("in_temp_raw", "215\n")]),
+                ("iio:device3", &[("in_temp_raw", "215\n")]),
+                ("trigger0", &[("in_temp_raw", "215\n")]),
+            ],
+        );
+        let mut config = Config::default();
+        config.sensors.insert(
+            "iio:device3".to_string(),
+            SensorConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        );
+        let sensors = source(&path, config).discover();
+        fs::remove_dir_all(&path).unwrap();
+
+        let sensors: Vec<(String, String)> = sensors
+            .unwrap()
+            .into_iter()
+            .map(|sensor| (sensor.id, sensor.chip))
+            .collect();
+        assert_eq!(
+            sensors,
+            [
+                ("iio:device0".to_string(), "bme280".to_string()),
+                ("iio:device2".to_string(), "unknown".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn converts_to_exported_units() {
+        let path = devices(
+            "iio-read",
+            &[
+                (
+                    "iio:device0",
+                    &[
+                        ("in_temp_input", "23125\n"),
+                        ("in_humidityrelative_input", "45500\n"),
+                        ("in_pressure_input", "101.325\n"),
+                    ],
+                ),
+                (
+                    "iio:device1",
+                    &[("in_temp_raw", "2150\n"), ("in_temp_scale", "10\n")],
+                ),
+                ("iio:device2", &[("in_humidityrelative_input", "45500\n")]),
+            ],
+        );
+        let source = source(&path, Config::default());
+        let bme280 = source.read(&sensor("iio:device0"), &mut || {});
+        let raw = source.read(&sensor("iio:device1"), &mut || {});
+        let no_temperature = source.read(&sensor("iio:device2"), &mut || {});
+        fs::remove_dir_all(&path).unwrap();
+
+        let bme280 = bme280.unwrap();
+        assert_eq!(bme280.celsius, 23.125);
+        assert_eq!(bme280.humidity, Some(45.5));
+        assert_eq!(bme280.pressure, Some(101325.0));
+        let raw = raw.unwrap();
+        assert_eq!(raw.celsius, 21.5);
+        assert_eq!((raw.humidity, raw.pressure), (None, None));
+        assert!(matches!(no_temperature, Err(ReadError::Io(_))));
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Measurement, ReadError, Sensor, SensorSource};
+use crate::{
+    config::{Config, OwserverConfig},
+    owserver::Client,
+    w1,
+};
+use std::{io, sync::Arc};
+
+/// Sensors served by an OWFS owserver, e.g. on a DS9490R USB adapter.
+/// Paths are read under `/uncached` so every read starts a conversion.
+pub struct OwserverSource {
+    name: String,
+    client: Client,
+    config: Arc<Config>,
+    families: Vec<String>,
+}
+
+impl OwserverSource {
+    pub fn new(config: Arc<Config>, server: &OwserverConfig) -> Self {
+        let client = Client::new(&server.address, server.timeout);
+        Self {
+            name: format!("owserver {}", client.address()),
+            client,
+            config,
+            families: server.families.clone(),
+        }
+    }
+
+    /// The sensor named by a directory entry such as `/uncached/28.0316A2791AFF`.
+    fn sensor(&self, entry: &str) -> Option<Sensor> {
+        let id = entry.trim_end_matches('/').rsplit('/').next()?;
+        let (code, _) = id.split_once('.')?;
+        let family = w1::family::lookup(code)?;
+        if !self
+            .families
+            .iter()
+            .any(|selected| selected.eq_ignore_ascii_case(code))
+        {
+            return None;
+        }
+        Some(Sensor {
+            id: id.to_string(),
+            source: "owserver",
+            family: code.to_string(),
+            chip: family.chip.to_string(),
+            bus: Some(self.client.address().to_string()),
+        })
+    }
+}
+
+impl SensorSource for OwserverSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn discover(&self) -> io::Result<Vec<Sensor>> {
+        let mut sensors: Vec<Sensor> = self
+            .client
+            .dir("/uncached/")?
+            .iter()
+            .filter_map(|entry| self.sensor(entry))
+            .filter(|sensor| self.config.sensor(&sensor.id).enabled)
+            .collect();
+        sensors.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(sensors)
+    }
+
+    fn read(
+        &self,
+        sensor: &Sensor,
+        _on_crc_failure: &mut dyn FnMut(),
+    ) -> Result<Measurement, ReadError> {
+        let value = self
+            .client
+            .read(&format!("/uncached/{}/temperature", sensor.id))?;
+        let celsius = value.trim().parse().map_err(|e| {
+            ReadError::Parse(format!("Invalid temperature {:?}: {}", value.trim(), e))
+        })?;
+        Ok(Measurement {
+            celsius,
+            resolution: None,
+            humidity: None,
+            pressure: None,
+        })
+    }
+
+    fn check(
+        &self,
+        measurement: Measurement,
+        previous: Option<f64>,
+    ) -> Result<Measurement, ReadError> {
+        w1::check_sentinel(measurement, previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::SensorConfig,
+        owserver::{fake, MSG_DIRALL, MSG_READ},
+    };
+
+    fn source(address: String, config: Config) -> OwserverSource {
+        let server = OwserverConfig {
+            address,
+            families: vec!["28".to_string(), "10".to_string()],
+            ..OwserverConfig::default()
+        };
+        OwserverSource::new(Arc::new(config), &server)
+    }
+
+    #[test]
+    fn discovers_selected_and_enabled_sensors() {
+        let address = fake::serve(|request, _| {
+            assert_eq!(request.kind, MSG_DIRALL);
+            let listing = [
+                "/uncached/28.0316A2791AFF",
+                "/uncached/10.A8F4E2000800",
+                "/uncached/28.000000000001",
+                // Not selected, unknown, and not a device.
+                "/uncached/22.0000000000AA",
+                "/uncached/05.4AEC29CDBAAB",
+                "/uncached/bus.0",
+                "/uncached/settings",
+            ]
+            .join(",");
+            vec![fake::message(0, listing.as_bytes())]
+        });
+        let mut config = Config::default();
+        config.sensors.insert(
+            "28.000000000001".to_string(),
+            SensorConfig {
+                enabled: false,
+                ..SensorConfig::default()
+            },
+        );
+
+        let source = source(address.clone(), config);
+        let sensors = source.discover().unwrap();
+        let ids: Vec<&str> = sensors.iter().map(|sensor| sensor.id.as_str()).collect();
+        assert_eq!(ids, ["10.A8F4E2000800", "28.0316A2791AFF"]);
+        assert_eq!(sensors[1].family, "28");
+        assert_eq!(sensors[1].chip, "DS18B20");
+        assert_eq!(sensors[1].source, "owserver");
+        assert_eq!(sensors[1].bus.as_deref(), Some(address.as_str()));
+    }
+
+    #[test]
+    fn reads_uncached_temperature() {
+        let address = fake::serve(|request, path| {
+            assert_eq!(request.kind, MSG_READ);
+            assert_eq!(path, "/uncached/28.0316A2791AFF/temperature");
+            vec![fake::message(12, b"    -10.0625")]
+        });
+        let source = source(address, Config::default());
+        let sensor = source.sensor("/uncached/28.0316A2791AFF").unwrap();
+        let measurement = source.read(&sensor, &mut || {}).unwrap();
+        assert_eq!(measurement.celsius, -10.0625);
+    }
+
+    #[test]
+    fn rejects_unparsable_temperature() {
+        let address = fake::serve(|_, _| vec![fake::message(3, b"n/a")]);
+        let source = source(address, Config::default());
+        let sensor = source.sensor("/uncached/28.0316A2791AFF").unwrap();
+        assert!(matches!(
+            source.read(&sensor, &mut || {}),
+            Err(ReadError::Parse(_))
+        ));
+    }
+}
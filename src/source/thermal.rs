@@ -0,0 +1,107 @@
+use super::{read_millidegrees, read_name, Measurement, ReadError, Sensor, SensorSource};
+use crate::config::Config;
+use std::{fs, io, sync::Arc};
+
+/// The kernel's thermal zones, under `/sys/class/thermal/thermal_zone*`.
+///
+/// Sensors are identified by their zone directory, e.g. `thermal_zone0`.
+/// The zone's `type` file, e.g. `cpu-thermal`, is exported as the `chip`
+/// label.
+pub struct ThermalSource {
+    config: Arc<Config>,
+}
+
+impl ThermalSource {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+}
+
+impl SensorSource for ThermalSource {
+    fn name(&self) -> &str {
+        "thermal"
+    }
+
+    fn discover(&self) -> io::Result<Vec<Sensor>> {
+        let mut sensors = Vec::new();
+        for entry in fs::read_dir(&self.config.thermal.path)? {
+            let entry = entry?;
+            let Some(zone) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !zone.starts_with("thermal_zone") || !entry.path().join("temp").exists() {
+                continue;
+            }
+            sensors.push(Sensor {
+                chip: read_name(&entry.path().join("type"))
+                    .unwrap_or_else(|_| "unknown".to_string()),
+                id: zone,
+                source: "thermal",
+                family: String::new(),
+                bus: None,
+            });
+        }
+        sensors.retain(|sensor| self.config.sensor(&sensor.id).enabled);
+        sensors.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(sensors)
+    }
+
+    fn read(
+        &self,
+        sensor: &Sensor,
+        _on_crc_failure: &mut dyn FnMut(),
+    ) -> Result<Measurement, ReadError> {
+        read_millidegrees(&self.config.thermal.path.join(&sensor.id).join("temp"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SensorConfig;
+    use std::process;
+
+    #[test]
+    fn discovers_zones() {
+        let path = std::env::temp_dir().join(format!("temperatures-{}-thermal", process::id()));
+        let files = [
+            ("thermal_zone0/type", "cpu-thermal\n"),
+            ("thermal_zone0/temp", "47236\n"),
+            ("thermal_zone1/temp", "-5000\n"),
+            ("thermal_zone2/type", "gpu-thermal\n"),
+            ("thermal_zone2/temp", "45000\n"),
+            ("thermal_zone3/type", "disabled\n"),
+            ("cooling_device0/type", "fan\n"),
+        ];
+        for (file, content) in files {
+            fs::create_dir_all(path.join(file).parent().unwrap()).unwrap();
+            fs::write(path.join(file), content).unwrap();
+        }
+        let mut config = Config::default();
+        config.thermal.path = path.clone();
+        config.sensors.insert(
+            "thermal_zone2".to_string(),
+            SensorConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        );
+        let source = ThermalSource::new(Arc::new(config));
+        let sensors = source.discover().unwrap();
+        let reading = source.read(&sensors[1], &mut || {});
+        fs::remove_dir_all(&path).unwrap();
+
+        let sensors: Vec<(&str, &str)> = sensors
+            .iter()
+            .map(|sensor| (sensor.id.as_str(), sensor.chip.as_str()))
+            .collect();
+        assert_eq!(
+            sensors,
+            [
+                ("thermal_zone0", "cpu-thermal"),
+                ("thermal_zone1", "unknown")
+            ]
+        );
+        assert_eq!(reading.unwrap().celsius, -5.0);
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Measurement, Metadata, ReadError, Sensor, SensorSource};
+use crate::{config::Config, hotplug, w1};
+use std::{
+    io,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the conversion state is checked during a bulk read.
+const BULK_READ_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Sensors on the kernel's w1 bus masters, read through sysfs.
+pub struct W1Source {
+    config: Arc<Config>,
+}
+
+impl W1Source {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+
+    fn slave(&self, sensor: &Sensor) -> w1::Slave {
+        w1::Slave {
+            id: sensor.id.clone(),
+            path: self.config.w1.devices_path.join(&sensor.id),
+            bus: sensor.bus.clone(),
+        }
+    }
+
+    /// Triggers a simultaneous conversion on every bus master that supports
+    /// it and waits for all of them to finish, so the following per-sensor
+    /// reads return immediately. Gives up after `w1.read_timeout`; sensors
+    /// that are still converting then fall back to a conversion of their own.
+    fn bulk_convert(&self) {
+        let masters = match w1::bus_masters(&self.config.w1.devices_path) {
+            Ok(masters) => masters,
+            Err(e) => {
+                eprintln!("Failed to list bus masters: {}", e);
+                return;
+            }
+        };
+        let mut pending: Vec<_> = masters
+            .into_iter()
+            .filter(|master| master.supports_bulk_read())
+            .filter(|master| match master.trigger_bulk_read() {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("Failed to trigger bulk read on {}: {}", master.name, e);
+                    false
+                }
+            })
+            .collect();
+
+        let deadline = Instant::now() + self.config.w1.read_timeout;
+        while !pending.is_empty() {
+            thread::sleep(BULK_READ_POLL_INTERVAL);
+            pending.retain(|master| match master.bulk_read_status() {
+                Ok(status) => status == w1::BulkReadStatus::Converting,
+                Err(e) => {
+                    eprintln!("Failed to read bulk read state of {}: {}", master.name, e);
+                    false
+                }
+            });
+
+            if Instant::now() >= deadline {
+                for master in &pending {
+                    eprintln!("Bulk conversion on {} did not finish in time", master.name);
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn sensor(slave: &w1::Slave) -> Sensor {
+    Sensor {
+        id: slave.id.clone(),
+        source: "w1",
+        family: slave.family_code().to_string(),
+        chip: slave
+            .family()
+            .map_or("unknown", |family| family.chip)
+            .to_string(),
+        bus: slave.bus.clone(),
+    }
+}
+
+impl SensorSource for W1Source {
+    fn name(&self) -> &str {
+        "w1"
+    }
+
+    fn discover(&self) -> io::Result<Vec<Sensor>> {
+        Ok(w1::discover(&self.config.w1.devices_path)?
+            .iter()
+            .filter(|slave| self.config.is_polled(slave))
+            .map(sensor)
+            .collect())
+    }
+
+    fn read(
+        &self,
+        sensor: &Sensor,
+        on_crc_failure: &mut dyn FnMut(),
+    ) -> Result<Measurement, ReadError> {
+        w1::read_temperature_retrying(
+            &self.slave(sensor),
+            self.config.w1.crc_retries,
+            on_crc_failure,
+        )
+    }
+
+    fn check(
+        &self,
+        measurement: Measurement,
+        previous: Option<f64>,
+    ) -> Result<Measurement, ReadError> {
+        w1::check_sentinel(measurement, previous)
+    }
+
+    fn setup(&self, sensor: &Sensor) -> io::Result<()> {
+        let slave = self.slave(sensor);
+        w1::reconcile(&slave, &self.config.target_settings(&slave))
+    }
+
+    fn metadata(&self, sensor: &Sensor) -> io::Result<Metadata> {
+        let slave = self.slave(sensor);
+        Ok(Metadata {
+            resolution: slave.resolution()?,
+            external_power: slave.external_power()?,
+        })
+    }
+
+    fn metadata_interval(&self) -> Option<Duration> {
+        Some(self.config.w1.power_check_interval)
+    }
+
+    fn bus_master(&self, sensor: &Sensor) -> Option<String> {
+        sensor.bus.clone()
+    }
+
+    fn prepare(&self) {
+        if self.config.w1.bulk_read {
+            self.bulk_convert();
+        }
+    }
+
+    fn watch(&self) -> io::Result<Option<hotplug::Watcher>> {
+        if !self.config.w1.hotplug {
+            return Ok(None);
+        }
+        let config = self.config.clone();
+        let devices_path = config.w1.devices_path.clone();
+        let events = hotplug::watch("w1", move |name| {
+            w1::slave(&devices_path, name)
+                .filter(|slave| config.is_polled(slave))
+                .map(|slave| sensor(&slave))
+        })?;
+        Ok(Some(hotplug::Watcher {
+            events,
+            resync_interval: self.config.w1.resync_interval,
+        }))
+    }
+}
@@ -0,0 +1,395 @@
+use crate::{
+    config::{Config, SupervisorConfig},
+    poller::TrackedSensor,
+    w1,
+};
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+use tokio::{process, task, time};
+
+/// What the supervisor does to a bus on which every sensor keeps failing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Ask the w1 core to search the bus for slaves again.
+    Rescan,
+    /// Run the configured recovery command.
+    Command,
+}
+
+impl Action {
+    /// Value of the `action` label.
+    fn label(self) -> &'static str {
+        match self {
+            Action::Rescan => "rescan",
+            Action::Command => "command",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BusState {
+    /// Consecutive polls in which every sensor of the bus failed, counted
+    /// since the last recovery action.
+    failed_polls: u32,
+    /// Recovery actions taken since the bus last worked.
+    actions_taken: u32,
+    last_action: Option<Instant>,
+}
+
+/// Watches the health of every bus and tries to recover buses that stopped
+/// answering: first with a rescan, then with the recovery command if one is
+/// configured. Actions on a bus are at least `min_interval` apart.
+pub struct BusSupervisor {
+    buses: HashMap<String, BusState>,
+    recoveries: IntCounterVec,
+}
+
+impl BusSupervisor {
+    pub fn new(registry: &Registry) -> Self {
+        let opts = Opts::new(
+            "w1_bus_recoveries_total",
+            "Recovery actions taken on a bus whose sensors all failed",
+        );
+        let recoveries = IntCounterVec::new(opts, &["bus", "action"]).unwrap();
+        registry.register(Box::new(recoveries.clone())).unwrap();
+        Self {
+            buses: HashMap::new(),
+            recoveries,
+        }
+    }
+
+    /// Records the result of a poll that read the sensors in `read` and
+    /// returns the recovery actions that are due. A bus counts as failed when
+    /// none of its sensors read in the poll that ended at `now` succeeded and
+    /// none of its other sensors is present. Sensors missing from the bus
+    /// count as failed: the w1 core detaches the slaves of a wedged bus after
+    /// a few failed searches, which would otherwise hide the failure. Buses
+    /// with neither a read nor a missing sensor are left as they are. No
+    /// action is taken on buses in `recovering`, whose previous action is
+    /// still running.
+    pub fn record_poll(
+        &mut self,
+        config: &SupervisorConfig,
+        sensors: &HashMap<String, TrackedSensor>,
+        read: &HashSet<String>,
+        recovering: &HashSet<String>,
+        now: Instant,
+    ) -> Vec<(String, Action)> {
+        let known: HashSet<&str> = sensors
+            .values()
+            .filter_map(|sensor| sensor.bus.as_deref())
+            .collect();
+        self.buses.retain(|bus, _| known.contains(bus.as_str()));
+
+        let mut buses: HashMap<&str, bool> = HashMap::new();
+        for (id, sensor) in sensors {
+            let Some(bus) = sensor.bus.as_deref() else {
+                continue;
+            };
+            let ok = if read.contains(id) {
+                sensor.last_success == Some(now)
+            } else if !sensor.present {
+                false
+            } else {
+                continue;
+            };
+            *buses.entry(bus).or_default() |= ok;
+        }
+
+        let mut actions = Vec::new();
+        for (bus, any_ok) in buses {
+            let state = self.buses.entry(bus.to_string()).or_default();
+            if any_ok {
+                if state.actions_taken > 0 {
+                    println!("Bus {} recovered", bus);
+                }
+                *state = BusState::default();
+                continue;
+            }
+
+            state.failed_polls += 1;
+            if state.failed_polls < config.failure_threshold || recovering.contains(bus) {
+                continue;
+            }
+            if state
+                .last_action
+                .is_some_and(|last| now.duration_since(last) < config.min_interval)
+            {
+                continue;
+            }
+
+            let action = if state.actions_taken > 0 && config.recovery_command.is_some() {
+                Action::Command
+            } else {
+                Action::Rescan
+            };
+            eprintln!(
+                "Every sensor on {} failed {} polls in a row, running {}",
+                bus,
+                state.failed_polls,
+                action.label()
+            );
+            state.failed_polls = 0;
+            state.actions_taken += 1;
+            state.last_action = Some(now);
+            self.recoveries
+                .with_label_values(&[bus, action.label()])
+                .inc();
+            actions.push((bus.to_string(), action));
+        }
+        actions
+    }
+}
+
+/// Carries out a recovery action on `bus`. Failures are only logged; the
+/// supervisor tries again after `min_interval` if the bus stays down.
+pub async fn run_action(config: &Config, bus: &str, action: Action) {
+    match action {
+        Action::Rescan => {
+            let master = w1::BusMaster {
+                name: bus.to_string(),
+                path: config.w1.devices_path.join(bus),
+            };
+            match task::spawn_blocking(move || master.rescan()).await {
+                Ok(Ok(())) => println!("Triggered a rescan of {}", bus),
+                Ok(Err(e)) => eprintln!("Failed to rescan {}: {}", bus, e),
+                Err(e) => eprintln!("Rescan task for {} failed: {}", bus, e),
+            }
+        }
+        Action::Command => {
+            let Some(command) = &config.supervisor.recovery_command else {
+                return;
+            };
+            let args: Vec<String> = command
+                .iter()
+                .map(|arg| arg.replace("{bus}", bus))
+                .collect();
+            let mut child = process::Command::new(&args[0]);
+            child.args(&args[1..]).env("W1_BUS", bus).kill_on_drop(true);
+
+            match time::timeout(config.supervisor.command_timeout, child.status()).await {
+                Ok(Ok(status)) if status.success() => {
+                    println!("Recovery command for {} succeeded", bus)
+                }
+                Ok(Ok(status)) => eprintln!("Recovery command for {} failed: {}", bus, status),
+                Ok(Err(e)) => eprintln!("Failed to run the recovery command for {}: {}", bus, e),
+                Err(_) => eprintln!("Recovery command for {} timed out", bus),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metrics::SensorMetrics, source::Sensor};
+    use std::time::Duration;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    struct Bus {
+        supervisor: BusSupervisor,
+        sensors: HashMap<String, TrackedSensor>,
+        config: SupervisorConfig,
+        start: Instant,
+    }
+
+    impl Bus {
+        /// A supervisor watching `w1_bus_master1` with the sensors `ids`.
+        fn new(ids: &[&str]) -> Self {
+            let registry = Registry::new();
+            let start = Instant::now();
+            let sensors = ids
+                .iter()
+                .map(|id| {
+                    let sensor = Sensor {
+                        id: id.to_string(),
+                        source: "w1",
+                        family: "28".to_string(),
+                        chip: "DS18B20".to_string(),
+                        bus: Some("w1_bus_master1".to_string()),
+                    };
+                    let tracked = TrackedSensor {
+                        metrics: SensorMetrics::new(&registry, &sensor, Default::default()),
+                        bus: sensor.bus.clone(),
+                        sensor,
+                        last_seen: start,
+                        last_success: None,
+                        present: true,
+                    };
+                    (id.to_string(), tracked)
+                })
+                .collect();
+            Self {
+                supervisor: BusSupervisor::new(&registry),
+                sensors,
+                config: SupervisorConfig {
+                    enabled: true,
+                    ..Default::default()
+                },
+                start,
+            }
+        }
+
+        /// Records a poll `minutes` after the start in which every sensor
+        /// was read and the sensors in `ok` succeeded.
+        fn poll(&mut self, minutes: u32, ok: &[&str]) -> Vec<(String, Action)> {
+            let now = self.start + MINUTE * minutes;
+            for (id, sensor) in &mut self.sensors {
+                if ok.contains(&id.as_str()) {
+                    sensor.last_success = Some(now);
+                }
+            }
+            let read = self.sensors.keys().cloned().collect();
+            self.supervisor
+                .record_poll(&self.config, &self.sensors, &read, &HashSet::new(), now)
+        }
+
+        fn recoveries(&self, action: Action) -> u64 {
+            self.supervisor
+                .recoveries
+                .with_label_values(&["w1_bus_master1", action.label()])
+                .get()
+        }
+    }
+
+    fn rescan() -> Vec<(String, Action)> {
+        vec![("w1_bus_master1".to_string(), Action::Rescan)]
+    }
+
+    #[test]
+    fn acts_after_the_failure_threshold() {
+        let mut bus = Bus::new(&["28-a", "28-b"]);
+        assert!(bus.poll(1, &[]).is_empty());
+        assert!(bus.poll(2, &[]).is_empty());
+        assert_eq!(bus.poll(3, &[]), rescan());
+        assert_eq!(bus.recoveries(Action::Rescan), 1);
+    }
+
+    #[test]
+    fn one_working_sensor_keeps_the_bus_healthy() {
+        let mut bus = Bus::new(&["28-a", "28-b"]);
+        for minute in 1..=5 {
+            assert!(bus.poll(minute, &["28-b"]).is_empty());
+        }
+    }
+
+    #[test]
+    fn actions_are_rate_limited() {
+        let mut bus = Bus::new(&["28-a"]);
+        bus.config.min_interval = MINUTE * 10;
+        bus.poll(1, &[]);
+        bus.poll(2, &[]);
+        assert_eq!(bus.poll(3, &[]), rescan());
+        // The threshold is reached again, but the last action is too recent.
+        assert!(bus.poll(4, &[]).is_empty());
+        assert!(bus.poll(5, &[]).is_empty());
+        assert!(bus.poll(6, &[]).is_empty());
+        assert!(bus.poll(12, &[]).is_empty());
+        assert_eq!(bus.poll(13, &[]), rescan());
+        assert_eq!(bus.recoveries(Action::Rescan), 2);
+    }
+
+    #[test]
+    fn runs_the_command_after_a_rescan_did_not_help() {
+        let mut bus = Bus::new(&["28-a"]);
+        bus.config.min_interval = MINUTE;
+        bus.config.recovery_command = Some(vec!["true".to_string()]);
+        bus.poll(1, &[]);
+        bus.poll(2, &[]);
+        assert_eq!(bus.poll(3, &[]), rescan());
+        assert!(bus.poll(4, &[]).is_empty());
+        assert!(bus.poll(5, &[]).is_empty());
+        assert_eq!(
+            bus.poll(6, &[]),
+            [("w1_bus_master1".to_string(), Action::Command)]
+        );
+        assert_eq!(bus.recoveries(Action::Command), 1);
+    }
+
+    #[test]
+    fn keeps_rescanning_without_a_command() {
+        let mut bus = Bus::new(&["28-a"]);
+        bus.config.min_interval = MINUTE;
+        bus.poll(1, &[]);
+        bus.poll(2, &[]);
+        assert_eq!(bus.poll(3, &[]), rescan());
+        bus.poll(4, &[]);
+        bus.poll(5, &[]);
+        assert_eq!(bus.poll(6, &[]), rescan());
+    }
+
+    #[test]
+    fn recovery_resets_the_bus() {
+        let mut bus = Bus::new(&["28-a"]);
+        bus.config.min_interval = MINUTE;
+        bus.config.recovery_command = Some(vec!["true".to_string()]);
+        bus.poll(1, &[]);
+        bus.poll(2, &[]);
+        assert!(bus.poll(3, &["28-a"]).is_empty());
+        assert!(bus.poll(4, &[]).is_empty());
+        assert!(bus.poll(5, &[]).is_empty());
+        assert_eq!(bus.poll(6, &[]), rescan());
+        assert!(bus.poll(7, &["28-a"]).is_empty());
+        // The bus worked in between, so the next action is a rescan again.
+        bus.poll(8, &[]);
+        bus.poll(9, &[]);
+        assert_eq!(bus.poll(10, &[]), rescan());
+    }
+
+    #[test]
+    fn missing_sensors_count_as_failed() {
+        let mut bus = Bus::new(&["28-a", "28-b"]);
+        for sensor in bus.sensors.values_mut() {
+            sensor.present = false;
+        }
+        let mut poll = |minutes| {
+            bus.supervisor.record_poll(
+                &bus.config,
+                &bus.sensors,
+                &HashSet::new(),
+                &HashSet::new(),
+                bus.start + MINUTE * minutes,
+            )
+        };
+        assert!(poll(1).is_empty());
+        assert!(poll(2).is_empty());
+        assert_eq!(poll(3), rescan());
+    }
+
+    #[test]
+    fn present_sensors_that_were_not_read_are_ignored() {
+        let mut bus = Bus::new(&["28-a"]);
+        for minutes in 1..=5 {
+            let actions = bus.supervisor.record_poll(
+                &bus.config,
+                &bus.sensors,
+                &HashSet::new(),
+                &HashSet::new(),
+                bus.start + MINUTE * minutes,
+            );
+            assert!(actions.is_empty());
+        }
+    }
+
+    #[test]
+    fn waits_for_a_running_action() {
+        let mut bus = Bus::new(&["28-a"]);
+        let read = bus.sensors.keys().cloned().collect();
+        let recovering = HashSet::from(["w1_bus_master1".to_string()]);
+        for minutes in 1..=5 {
+            let actions = bus.supervisor.record_poll(
+                &bus.config,
+                &bus.sensors,
+                &read,
+                &recovering,
+                bus.start + MINUTE * minutes,
+            );
+            assert!(actions.is_empty());
+        }
+        assert_eq!(bus.poll(6, &[]), rescan());
+    }
+}
@@ -0,0 +1,359 @@
+mod bus;
+pub mod family;
+mod netlink;
+mod scratchpad;
+mod settings;
+
+pub use bus::{bus_masters, BulkReadStatus, BusMaster, BusStats};
+pub use family::{Family, FAMILIES};
+pub use netlink::alarm_search;
+use scratchpad::Scratchpad;
+pub use settings::{reconcile, TargetSettings};
+
+use crate::source::{Measurement, ReadError};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Values a DS18B20 returns when it has no real measurement to offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sentinel {
+    /// 85°C, the scratchpad content after power-on or a brown-out.
+    PowerOnReset,
+    /// -127°C, reported for a sensor that stopped answering.
+    Disconnected,
+    /// 127.9375°C, an all-ones scratchpad from a floating data line.
+    BusError,
+}
+
+impl Sentinel {
+    /// Value of the `reason` label for this sentinel.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Sentinel::PowerOnReset => "power_on_reset",
+            Sentinel::Disconnected => "disconnected",
+            Sentinel::BusError => "bus_error",
+        }
+    }
+}
+
+/// A slave device found under the w1 devices directory.
+#[derive(Debug, Clone)]
+pub struct Slave {
+    pub id: String,
+    pub path: PathBuf,
+    /// Name of the bus master the slave hangs off, e.g. `w1_bus_master1`.
+    pub bus: Option<String>,
+}
+
+impl Slave {
+    /// Family code of the slave, i.e. the part of the ID before the dash.
+    pub fn family_code(&self) -> &str {
+        self.id.split('-').next().unwrap_or_default()
+    }
+
+    /// The temperature family of the slave, or `None` for other devices.
+    pub fn family(&self) -> Option<&'static Family> {
+        family::lookup(self.family_code())
+    }
+
+    /// Name of the bus master, or `unknown` if it could not be resolved.
+    pub fn bus_name(&self) -> &str {
+        self.bus.as_deref().unwrap_or("unknown")
+    }
+}
+
+/// Lists every slave under `devices_path`, skipping the bus master entries.
+pub fn discover(devices_path: &Path) -> io::Result<Vec<Slave>> {
+    let mut slaves: Vec<Slave> = fs::read_dir(devices_path)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| slave(devices_path, &entry.file_name().to_string_lossy()))
+        .collect();
+    slaves.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(slaves)
+}
+
+/// The slave named `id` under `devices_path`, or `None` if `id` names a bus
+/// master or something else that is not a slave.
+pub fn slave(devices_path: &Path, id: &str) -> Option<Slave> {
+    if !is_slave_id(id) {
+        return None;
+    }
+    let path = devices_path.join(id);
+    Some(Slave {
+        bus: resolve_bus(&path),
+        id: id.to_string(),
+        path,
+    })
+}
+
+/// Whether `name`, an entry of the devices directory, is a slave ID.
+pub fn is_slave_id(name: &str) -> bool {
+    !name.starts_with("w1_bus_master") && name.contains('-')
+}
+
+/// Entries under `/sys/bus/w1/devices` are symlinks into the directory of
+/// their bus master, e.g. `/sys/devices/w1_bus_master1/28-0316a2791aff`.
+fn resolve_bus(path: &Path) -> Option<String> {
+    let target = fs::canonicalize(path).ok()?;
+    let bus = target.parent()?.file_name()?.to_str()?;
+    bus.starts_with("w1_bus_master").then(|| bus.to_string())
+}
+
+/// Reads the temperature of a sensor. The kernel's `temperature` attribute
+/// (Linux 5.10 and later) is used where it exists; the driver reports a
+/// failed CRC check on it as EIO, and the resolution comes from the
+/// `resolution` attribute. Older kernels only have `w1_slave`, whose
+/// scratchpad is decoded here.
+pub fn read_temperature(slave: &Slave) -> Result<Measurement, ReadError> {
+    let family = slave
+        .family()
+        .ok_or_else(|| ReadError::Parse(format!("Unsupported family {}", slave.family_code())))?;
+
+    match fs::read_to_string(slave.path.join("temperature")) {
+        Ok(content) => {
+            let celsius = parse_temperature_attribute(&content)?;
+            // The driver only has the attribute for families whose
+            // resolution can be set.
+            let resolution = if family.configurable_resolution {
+                slave.resolution().unwrap_or_else(|e| {
+                    eprintln!("Failed to read the resolution of {}: {}", slave.id, e);
+                    None
+                })
+            } else {
+                None
+            };
+            return Ok(Measurement {
+                celsius,
+                resolution,
+                humidity: None,
+                pressure: None,
+            });
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) if e.raw_os_error() == Some(libc::EIO) => return Err(ReadError::Crc),
+        Err(e) => return Err(e.into()),
+    }
+
+    let content = fs::read_to_string(slave.path.join("w1_slave"))?;
+    let scratchpad = parse_w1_slave(&content)?;
+    (family.decode)(&scratchpad)
+}
+
+/// Like [`read_temperature`], but reads again up to `retries` times when the
+/// CRC check fails. `on_crc_failure` is called for every failed check.
+pub fn read_temperature_retrying(
+    slave: &Slave,
+    retries: u32,
+    mut on_crc_failure: impl FnMut(),
+) -> Result<Measurement, ReadError> {
+    let mut attempt = 0;
+    loop {
+        match read_temperature(slave) {
+            Err(ReadError::Crc) => {
+                on_crc_failure();
+                if attempt == retries {
+                    return Err(ReadError::Crc);
+                }
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// An 85°C reading is only trusted when the previous accepted reading was
+/// within this many degrees of it; otherwise it is taken as a power-on reset.
+const POWER_ON_RESET_WINDOW: f64 = 10.0;
+
+/// Rejects `measurement` if it is a sentinel value rather than a real
+/// temperature. `previous` is the last accepted raw reading of the same
+/// sensor, if any.
+pub fn check_sentinel(
+    measurement: Measurement,
+    previous: Option<f64>,
+) -> Result<Measurement, ReadError> {
+    let temp = measurement.celsius;
+    if temp == 85.0 {
+        match previous {
+            Some(previous) if (85.0 - previous).abs() <= POWER_ON_RESET_WINDOW => Ok(measurement),
+            _ => Err(ReadError::Sentinel(Sentinel::PowerOnReset)),
+        }
+    } else if temp == -127.0 {
+        Err(ReadError::Sentinel(Sentinel::Disconnected))
+    } else if temp == 127.9375 {
+        Err(ReadError::Sentinel(Sentinel::BusError))
+    } else {
+        Ok(measurement)
+    }
+}
+
+/// Parses the `temperature` attribute, which holds millidegrees Celsius.
+fn parse_temperature_attribute(content: &str) -> Result<f64, ReadError> {
+    content
+        .trim()
+        .parse::<i32>()
+        .map(|millidegrees| f64::from(millidegrees) / 1000.0)
+        .map_err(|e| ReadError::Parse(format!("Invalid temperature {:?}: {}", content.trim(), e)))
+}
+
+/// Parses the two-line `w1_slave` output of the w1_therm driver and returns
+/// the scratchpad if it passed the CRC check:
+///
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+///
+/// The `t=` value of the second line is ignored; decoding the scratchpad
+/// ourselves gives the extended resolution of the DS18S20 and the configured
+/// resolution of the DS18B20 on kernels without the `temperature` attribute.
+fn parse_w1_slave(content: &str) -> Result<Scratchpad, ReadError> {
+    let crc_line = content
+        .lines()
+        .next()
+        .ok_or_else(|| ReadError::Parse("CRC line not found".into()))?;
+    match crc_line.split_whitespace().last() {
+        Some("YES") => {}
+        Some("NO") => return Err(ReadError::Crc),
+        _ => return Err(ReadError::Parse("CRC status not found".into())),
+    }
+    Scratchpad::parse(crc_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, process};
+
+    const SAMPLE: &str = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n\
+                          72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+
+    fn measurement(celsius: f64) -> Measurement {
+        Measurement {
+            celsius,
+            resolution: None,
+            humidity: None,
+            pressure: None,
+        }
+    }
+
+    /// A DS18B20 whose `w1_slave` file holds `content`, in a directory of
+    /// its own under the system temp dir.
+    fn slave(name: &str, content: &str) -> Slave {
+        let path = std::env::temp_dir().join(format!("temperatures-{}-{}", process::id(), name));
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("w1_slave"), content).unwrap();
+        Slave {
+            id: "28-0316a2791aff".to_string(),
+            path,
+            bus: None,
+        }
+    }
+
+    #[test]
+    fn parses_w1_slave() {
+        let scratchpad = parse_w1_slave(SAMPLE).unwrap();
+        assert_eq!(
+            scratchpad,
+            Scratchpad([0x72, 0x01, 0x4b, 0x46, 0x7f, 0xff, 0x0e, 0x10, 0x57])
+        );
+    }
+
+    #[test]
+    fn rejects_failed_crc() {
+        let content = "72 01 4b 46 7f ff 0e 10 00 : crc=57 NO\n\
+                       72 01 4b 46 7f ff 0e 10 00 t=23125\n";
+        assert!(matches!(parse_w1_slave(content), Err(ReadError::Crc)));
+    }
+
+    #[test]
+    fn rejects_malformed_w1_slave() {
+        assert!(matches!(parse_w1_slave(""), Err(ReadError::Parse(_))));
+        assert!(matches!(
+            parse_w1_slave("72 01 4b 46 7f ff 0e 10 57 : crc=57\n"),
+            Err(ReadError::Parse(_))
+        ));
+        assert!(matches!(
+            parse_w1_slave("72 01 4b : crc=57 YES\n"),
+            Err(ReadError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn reads_and_decodes_the_scratchpad() {
+        let slave = slave("read", SAMPLE);
+        let measurement = read_temperature(&slave).unwrap();
+        fs::remove_dir_all(&slave.path).unwrap();
+        assert_eq!(measurement.celsius, 23.125);
+        assert_eq!(measurement.resolution, Some(12));
+    }
+
+    #[test]
+    fn prefers_the_temperature_attribute() {
+        let slave = slave("attribute", SAMPLE);
+        fs::write(slave.path.join("temperature"), "-10125\n").unwrap();
+        fs::write(slave.path.join("resolution"), "11\n").unwrap();
+        let measurement = read_temperature(&slave).unwrap();
+        fs::write(slave.path.join("temperature"), "garbage\n").unwrap();
+        let malformed = read_temperature(&slave);
+        fs::remove_dir_all(&slave.path).unwrap();
+        assert_eq!(measurement.celsius, -10.125);
+        assert_eq!(measurement.resolution, Some(11));
+        assert!(matches!(malformed, Err(ReadError::Parse(_))));
+    }
+
+    #[test]
+    fn retries_crc_failures() {
+        let slave = slave(
+            "retry",
+            "72 01 4b 46 7f ff 0e 10 00 : crc=57 NO\n72 01 4b 46 7f ff 0e 10 00 t=23125\n",
+        );
+        let failures = Cell::new(0);
+        let result = read_temperature_retrying(&slave, 2, || failures.set(failures.get() + 1));
+        fs::remove_dir_all(&slave.path).unwrap();
+        assert!(matches!(result, Err(ReadError::Crc)));
+        assert_eq!(failures.get(), 3);
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        let slave = slave("missing", SAMPLE);
+        fs::remove_dir_all(&slave.path).unwrap();
+        let failures = Cell::new(0);
+        let result = read_temperature_retrying(&slave, 2, || failures.set(failures.get() + 1));
+        assert!(matches!(result, Err(ReadError::Io(_))));
+        assert_eq!(failures.get(), 0);
+    }
+
+    #[test]
+    fn rejects_sentinels() {
+        assert!(matches!(
+            check_sentinel(measurement(-127.0), None),
+            Err(ReadError::Sentinel(Sentinel::Disconnected))
+        ));
+        assert!(matches!(
+            check_sentinel(measurement(127.9375), None),
+            Err(ReadError::Sentinel(Sentinel::BusError))
+        ));
+        assert!(check_sentinel(measurement(23.125), None).is_ok());
+        assert!(check_sentinel(measurement(-55.0), None).is_ok());
+    }
+
+    #[test]
+    fn trusts_85_degrees_only_with_history() {
+        let power_on_reset = |previous| {
+            matches!(
+                check_sentinel(measurement(85.0), previous),
+                Err(ReadError::Sentinel(Sentinel::PowerOnReset))
+            )
+        };
+        assert!(power_on_reset(None));
+        assert!(power_on_reset(Some(21.5)));
+        assert!(power_on_reset(Some(74.9)));
+        assert!(!power_on_reset(Some(75.0)));
+        assert!(!power_on_reset(Some(84.5)));
+        assert!(!power_on_reset(Some(95.0)));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A w1 bus master, e.g. `w1_bus_master1`.
+#[derive(Debug, Clone)]
+pub struct BusMaster {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// State of a bulk conversion as reported by `therm_bulk_read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkReadStatus {
+    /// At least one sensor is still converting.
+    Converting,
+    /// Every sensor finished converting; some values have not been read yet.
+    Ready,
+    /// No bulk conversion is pending.
+    Idle,
+}
+
+/// Counters the w1 core keeps for a bus master.
+#[derive(Debug, Clone, Copy)]
+pub struct BusStats {
+    /// `w1_master_slave_count`: slaves found by the last search.
+    pub slave_count: u64,
+    /// `w1_master_attempts`: searches run since the master was registered.
+    pub attempts: u64,
+    /// `w1_master_search`: remaining automatic searches, -1 for unlimited.
+    pub search: i64,
+}
+
+/// Lists the bus masters under `devices_path`.
+pub fn bus_masters(devices_path: &Path) -> io::Result<Vec<BusMaster>> {
+    let mut masters: Vec<BusMaster> = fs::read_dir(devices_path)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with("w1_bus_master").then(|| BusMaster {
+                name,
+                path: entry.path(),
+            })
+        })
+        .collect();
+    masters.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(masters)
+}
+
+impl BusMaster {
+    /// The bus master number, as used by the w1 netlink connector.
+    pub fn id(&self) -> Option<u32> {
+        self.name.strip_prefix("w1_bus_master")?.parse().ok()
+    }
+
+    pub fn stats(&self) -> io::Result<BusStats> {
+        Ok(BusStats {
+            slave_count: self.read_attribute("w1_master_slave_count")?,
+            attempts: self.read_attribute("w1_master_attempts")?,
+            search: self.read_attribute("w1_master_search")?,
+        })
+    }
+
+    fn read_attribute<T: std::str::FromStr>(&self, name: &str) -> io::Result<T> {
+        let content = fs::read_to_string(self.path.join(name))?;
+        content.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected {} value {:?}", name, content.trim()),
+            )
+        })
+    }
+
+    /// Wakes up the w1 core to search the bus for slaves right away. The
+    /// search mode is kept: a bus with automatic searches disabled gets a
+    /// single search.
+    pub fn rescan(&self) -> io::Result<()> {
+        let search: i64 = self.read_attribute("w1_master_search")?;
+        let search = if search == 0 { 1 } else { search };
+        fs::write(self.path.join("w1_master_search"), search.to_string())
+    }
+
+    /// Whether the driver supports simultaneous conversion on this bus.
+    pub fn supports_bulk_read(&self) -> bool {
+        self.path.join("therm_bulk_read").exists()
+    }
+
+    /// Starts a temperature conversion on every sensor of the bus at once.
+    pub fn trigger_bulk_read(&self) -> io::Result<()> {
+        fs::write(self.path.join("therm_bulk_read"), "trigger")
+    }
+
+    pub fn bulk_read_status(&self) -> io::Result<BulkReadStatus> {
+        let content = fs::read_to_string(self.path.join("therm_bulk_read"))?;
+        match content.trim() {
+            "-1" => Ok(BulkReadStatus::Converting),
+            "1" => Ok(BulkReadStatus::Ready),
+            "0" => Ok(BulkReadStatus::Idle),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected therm_bulk_read value {:?}", other),
+            )),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use super::{
+    scratchpad::{decode_ds18b20, decode_ds18s20},
+    Measurement, ReadError, Scratchpad,
+};
+
+/// A 1-Wire temperature sensor family supported by the w1_therm driver.
+#[derive(Debug)]
+pub struct Family {
+    /// Family code, the part of the slave ID before the dash (e.g. `28`).
+    pub code: &'static str,
+    /// Chip name, exported as the `chip` label. Parts sharing a family code
+    /// that cannot be told apart are separated by slashes.
+    pub chip: &'static str,
+    /// Whether the resolution can be set between 9 and 12 bits, on at least
+    /// one of the parts.
+    pub configurable_resolution: bool,
+    /// Decodes the temperature from the scratchpad.
+    pub decode: fn(&Scratchpad) -> Result<Measurement, ReadError>,
+}
+
+/// Every family we know how to read.
+pub const FAMILIES: &[Family] = &[
+    Family {
+        code: "10",
+        chip: "DS18S20",
+        configurable_resolution: false,
+        decode: decode_ds18s20,
+    },
+    Family {
+        code: "22",
+        chip: "DS1822",
+        configurable_resolution: true,
+        decode: decode_ds18b20,
+    },
+    Family {
+        code: "28",
+        chip: "DS18B20",
+        configurable_resolution: true,
+        decode: decode_ds18b20,
+    },
+    Family {
+        code: "3b",
+        chip: "DS1825/MAX31826",
+        // Only the DS1825; the MAX31826 is fixed at 12 bits.
+        configurable_resolution: true,
+        decode: decode_ds18b20,
+    },
+    Family {
+        code: "42",
+        chip: "DS28EA00",
+        configurable_resolution: true,
+        decode: decode_ds18b20,
+    },
+];
+
+/// Looks up a family by its code. Codes are matched case-insensitively.
+pub fn lookup(code: &str) -> Option<&'static Family> {
+    FAMILIES.iter().find(|f| f.code.eq_ignore_ascii_case(code))
+}
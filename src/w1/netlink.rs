@@ -0,0 +1,317 @@
+//! Minimal client for the w1 netlink connector, used for the alarm search
+//! that sysfs does not expose. See Documentation/w1/w1-netlink.rst.
+
+use std::{io, mem, time::Duration};
+
+const NETLINK_CONNECTOR: libc::c_int = 11;
+const NLMSG_DONE: u16 = 3;
+const CN_W1_IDX: u32 = 3;
+const CN_W1_VAL: u32 = 1;
+const W1_MASTER_CMD: u8 = 4;
+const W1_CMD_ALARM_SEARCH: u8 = 3;
+
+const NLMSG_HDR_LEN: usize = 16;
+const CN_MSG_LEN: usize = 20;
+const W1_MSG_LEN: usize = 12;
+const W1_CMD_LEN: usize = 4;
+
+/// A netlink connector socket bound to the w1 subsystem.
+struct Socket(libc::c_int);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+impl Socket {
+    fn open(timeout: Duration) -> io::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM, NETLINK_CONNECTOR) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = Socket(fd);
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<()> {
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let ret = unsafe {
+            libc::sendto(
+                self.0,
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret =
+            unsafe { libc::recv(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+}
+
+/// Runs an alarm search on bus master `master` (the `N` of
+/// `w1_bus_masterN`) and returns the IDs of the slaves whose last
+/// conversion was outside their TH/TL thresholds, in sysfs notation.
+pub fn alarm_search(master: u32, timeout: Duration) -> io::Result<Vec<String>> {
+    let socket = Socket::open(timeout)?;
+    let seq = std::process::id();
+    socket.send(&alarm_search_request(master, seq))?;
+
+    let mut ids = Vec::new();
+    let mut buf = vec![0u8; 16384];
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no status reply from the w1 netlink connector",
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        if parse_reply(&buf[..len], &mut ids)? {
+            return Ok(ids);
+        }
+    }
+}
+
+fn alarm_search_request(master: u32, seq: u32) -> Vec<u8> {
+    let total = NLMSG_HDR_LEN + CN_MSG_LEN + W1_MSG_LEN + W1_CMD_LEN;
+    let mut buf = Vec::with_capacity(total);
+    // struct nlmsghdr
+    buf.extend_from_slice(&(total as u32).to_ne_bytes());
+    buf.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+    buf.extend_from_slice(&0u16.to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    // struct cn_msg, with ack set so the kernel sends a status reply
+    buf.extend_from_slice(&CN_W1_IDX.to_ne_bytes());
+    buf.extend_from_slice(&CN_W1_VAL.to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&((W1_MSG_LEN + W1_CMD_LEN) as u16).to_ne_bytes());
+    buf.extend_from_slice(&0u16.to_ne_bytes());
+    // struct w1_netlink_msg
+    buf.push(W1_MASTER_CMD);
+    buf.push(0);
+    buf.extend_from_slice(&(W1_CMD_LEN as u16).to_ne_bytes());
+    buf.extend_from_slice(&master.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    // struct w1_netlink_cmd
+    buf.push(W1_CMD_ALARM_SEARCH);
+    buf.push(0);
+    buf.extend_from_slice(&0u16.to_ne_bytes());
+    buf
+}
+
+/// Collects the slave IDs in one datagram into `ids`. Returns `true` once
+/// the status reply for the search arrived.
+fn parse_reply(mut buf: &[u8], ids: &mut Vec<String>) -> io::Result<bool> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated w1 netlink reply");
+    let mut done = false;
+
+    while buf.len() >= NLMSG_HDR_LEN {
+        let nl_len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        if nl_len < NLMSG_HDR_LEN || nl_len > buf.len() {
+            return Err(invalid());
+        }
+        let msg = &buf[NLMSG_HDR_LEN..nl_len];
+        // Messages are padded to 4 bytes, except possibly the last one.
+        buf = &buf[((nl_len + 3) & !3).min(buf.len())..];
+
+        if msg.len() < CN_MSG_LEN {
+            return Err(invalid());
+        }
+        let idx = u32::from_ne_bytes(msg[0..4].try_into().unwrap());
+        let val = u32::from_ne_bytes(msg[4..8].try_into().unwrap());
+        let cn_len = u16::from_ne_bytes(msg[16..18].try_into().unwrap()) as usize;
+        if idx != CN_W1_IDX || val != CN_W1_VAL {
+            continue;
+        }
+        let mut data = msg
+            .get(CN_MSG_LEN..CN_MSG_LEN + cn_len)
+            .ok_or_else(invalid)?;
+
+        while data.len() >= W1_MSG_LEN {
+            let (msg_type, status) = (data[0], data[1]);
+            let w1_len = u16::from_ne_bytes(data[2..4].try_into().unwrap()) as usize;
+            let mut cmds = data
+                .get(W1_MSG_LEN..W1_MSG_LEN + w1_len)
+                .ok_or_else(invalid)?;
+            data = &data[W1_MSG_LEN + w1_len..];
+            if msg_type != W1_MASTER_CMD {
+                continue;
+            }
+            if status != 0 {
+                return Err(io::Error::from_raw_os_error(status.into()));
+            }
+
+            while cmds.len() >= W1_CMD_LEN {
+                let cmd = cmds[0];
+                let cmd_len = u16::from_ne_bytes(cmds[2..4].try_into().unwrap()) as usize;
+                let payload = cmds
+                    .get(W1_CMD_LEN..W1_CMD_LEN + cmd_len)
+                    .ok_or_else(invalid)?;
+                cmds = &cmds[W1_CMD_LEN + cmd_len..];
+                if cmd != W1_CMD_ALARM_SEARCH {
+                    continue;
+                }
+                // Status replies repeat the command without its data.
+                if cmd_len == 0 {
+                    done = true;
+                }
+                ids.extend(payload.chunks_exact(8).map(format_rom_id));
+            }
+        }
+    }
+    Ok(done)
+}
+
+/// Formats a 64-bit ROM code (family, 48-bit serial LSB first, CRC) the way
+/// sysfs names slaves, e.g. `28-0316a2791aff`.
+fn format_rom_id(rom: &[u8]) -> String {
+    let serial = rom[1..7]
+        .iter()
+        .rev()
+        .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+    format!("{:02x}-{:012x}", rom[0], serial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 8] = [0x28, 0xff, 0x1a, 0x79, 0xa2, 0x16, 0x03, 0x9c];
+
+    /// A connector message from the kernel carrying one w1 master command
+    /// with `data`, optionally followed by `trailing` bytes of padding.
+    fn reply(status: u8, data: &[u8], trailing: usize) -> Vec<u8> {
+        let w1_len = W1_CMD_LEN + data.len();
+        let cn_len = W1_MSG_LEN + w1_len;
+        let nl_len = NLMSG_HDR_LEN + CN_MSG_LEN + cn_len;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(nl_len as u32).to_ne_bytes());
+        buf.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+        buf.extend_from_slice(&[0; 10]);
+        buf.extend_from_slice(&CN_W1_IDX.to_ne_bytes());
+        buf.extend_from_slice(&CN_W1_VAL.to_ne_bytes());
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&(cn_len as u16).to_ne_bytes());
+        buf.extend_from_slice(&[0; 2]);
+        buf.extend_from_slice(&[W1_MASTER_CMD, status]);
+        buf.extend_from_slice(&(w1_len as u16).to_ne_bytes());
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&[W1_CMD_ALARM_SEARCH, 0]);
+        buf.extend_from_slice(&(data.len() as u16).to_ne_bytes());
+        buf.extend_from_slice(data);
+        buf.resize(buf.len() + trailing, 0);
+        buf
+    }
+
+    #[test]
+    fn builds_alarm_search_request() {
+        let buf = alarm_search_request(2, 42);
+        assert_eq!(buf.len(), 52);
+        assert_eq!(u32::from_ne_bytes(buf[0..4].try_into().unwrap()), 52);
+        assert_eq!(u32::from_ne_bytes(buf[8..12].try_into().unwrap()), 42);
+        assert_eq!(
+            u32::from_ne_bytes(buf[16..20].try_into().unwrap()),
+            CN_W1_IDX
+        );
+        assert_eq!(
+            u32::from_ne_bytes(buf[20..24].try_into().unwrap()),
+            CN_W1_VAL
+        );
+        assert_eq!(u16::from_ne_bytes(buf[32..34].try_into().unwrap()), 16);
+        assert_eq!(buf[36], W1_MASTER_CMD);
+        assert_eq!(u32::from_ne_bytes(buf[40..44].try_into().unwrap()), 2);
+        assert_eq!(buf[48], W1_CMD_ALARM_SEARCH);
+    }
+
+    #[test]
+    fn formats_rom_ids_like_sysfs() {
+        assert_eq!(format_rom_id(&ROM), "28-0316a2791aff");
+    }
+
+    #[test]
+    fn collects_ids_until_the_status_reply() {
+        let mut ids = Vec::new();
+        let data: Vec<u8> = ROM.iter().chain(&ROM).copied().collect();
+        assert!(!parse_reply(&reply(0, &data, 0), &mut ids).unwrap());
+        assert_eq!(ids, ["28-0316a2791aff", "28-0316a2791aff"]);
+
+        let mut datagram = reply(0, &ROM, 0);
+        datagram.extend(reply(0, &[], 0));
+        let mut ids = Vec::new();
+        assert!(parse_reply(&datagram, &mut ids).unwrap());
+        assert_eq!(ids, ["28-0316a2791aff"]);
+    }
+
+    #[test]
+    fn accepts_an_unpadded_last_message() {
+        // 3 bytes of data leave the message 2 bytes short of alignment.
+        let mut ids = Vec::new();
+        let datagram = reply(0, &[0; 3], 0);
+        assert_ne!(datagram.len() % 4, 0);
+        assert!(!parse_reply(&datagram, &mut ids).unwrap());
+        assert!(ids.is_empty());
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut ids = Vec::new();
+        let error = parse_reply(&reply(libc::ENODEV as u8, &[], 0), &mut ids).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ENODEV));
+
+        let truncated = &reply(0, &ROM, 0)[..40];
+        assert_eq!(
+            parse_reply(truncated, &mut ids).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
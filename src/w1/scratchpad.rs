@@ -0,0 +1,179 @@
+use super::{Measurement, ReadError, Sentinel};
+
+/// The 9-byte scratchpad of a temperature sensor, as dumped by the driver in
+/// the first line of `w1_slave`. The last byte is the CRC of the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scratchpad(pub [u8; 9]);
+
+impl Scratchpad {
+    /// Parses the hex bytes in front of the `:` of a `w1_slave` CRC line,
+    /// e.g. `72 01 4b 46 7f ff 0e 10 57 : crc=57 YES`.
+    pub fn parse(line: &str) -> Result<Self, ReadError> {
+        let hex = line.split(':').next().unwrap_or_default();
+        let bytes = hex
+            .split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|e| ReadError::Parse(format!("Invalid scratchpad {:?}: {}", hex, e)))?;
+        let bytes: [u8; 9] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            ReadError::Parse(format!("Scratchpad has {} bytes instead of 9", bytes.len()))
+        })?;
+        Ok(Self(bytes))
+    }
+
+    /// The raw temperature register.
+    fn temperature_register(&self) -> i16 {
+        i16::from_le_bytes([self.0[0], self.0[1]])
+    }
+}
+
+/// Decodes the scratchpad of a DS18B20 and its relatives (DS1822, DS1825,
+/// DS28EA00): a 12-bit two's complement value in 1/16°C, with the resolution
+/// in bits 5 and 6 of the configuration register. Bits below the configured
+/// resolution are undefined and masked off.
+pub fn decode_ds18b20(scratchpad: &Scratchpad) -> Result<Measurement, ReadError> {
+    check_blank(scratchpad)?;
+    let resolution = 9 + ((scratchpad.0[4] >> 5) & 0b11);
+    let undefined_bits = 12 - resolution;
+    let raw = scratchpad.temperature_register() & !((1 << undefined_bits) - 1);
+    Ok(Measurement {
+        celsius: f64::from(raw) / 16.0,
+        resolution: Some(resolution),
+        humidity: None,
+        pressure: None,
+    })
+}
+
+/// Decodes the scratchpad of a DS18S20/DS1820. The temperature register only
+/// has 0.5°C steps; COUNT_REMAIN (byte 6) and COUNT_PER_C (byte 7) give the
+/// extended resolution described in the datasheet:
+/// `TEMP_READ - 0.25 + (COUNT_PER_C - COUNT_REMAIN) / COUNT_PER_C`.
+pub fn decode_ds18s20(scratchpad: &Scratchpad) -> Result<Measurement, ReadError> {
+    check_blank(scratchpad)?;
+    let raw = scratchpad.temperature_register();
+    let count_remain = f64::from(scratchpad.0[6]);
+    let count_per_c = f64::from(scratchpad.0[7]);
+    let celsius = if count_per_c == 0.0 {
+        f64::from(raw) / 2.0
+    } else {
+        f64::from(raw >> 1) - 0.25 + (count_per_c - count_remain) / count_per_c
+    };
+    Ok(Measurement {
+        celsius,
+        resolution: None,
+        humidity: None,
+        pressure: None,
+    })
+}
+
+/// Rejects scratchpads that did not come from a sensor: all zeroes (which
+/// has a valid CRC) when nothing answered, all ones from a floating data line.
+fn check_blank(scratchpad: &Scratchpad) -> Result<(), ReadError> {
+    if scratchpad.0.iter().all(|&byte| byte == 0x00) {
+        return Err(ReadError::Sentinel(Sentinel::Disconnected));
+    }
+    if scratchpad.0.iter().all(|&byte| byte == 0xff) {
+        return Err(ReadError::Sentinel(Sentinel::BusError));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ds18b20(lsb: u8, msb: u8, config: u8) -> Scratchpad {
+        Scratchpad([lsb, msb, 0x4b, 0x46, config, 0xff, 0x0e, 0x10, 0x00])
+    }
+
+    fn ds18s20(lsb: u8, msb: u8, count_remain: u8) -> Scratchpad {
+        Scratchpad([lsb, msb, 0x4b, 0x46, 0xff, 0xff, count_remain, 0x10, 0x00])
+    }
+
+    #[test]
+    fn parses_crc_line() {
+        let scratchpad = Scratchpad::parse("72 01 4b 46 7f ff 0e 10 57 : crc=57 YES").unwrap();
+        assert_eq!(
+            scratchpad.0,
+            [0x72, 0x01, 0x4b, 0x46, 0x7f, 0xff, 0x0e, 0x10, 0x57]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_scratchpad() {
+        assert!(matches!(
+            Scratchpad::parse("72 01 4b 46 7f : crc=57 YES"),
+            Err(ReadError::Parse(_))
+        ));
+        assert!(matches!(
+            Scratchpad::parse("72 01 4b 46 7f ff 0e 10 zz : crc=57 YES"),
+            Err(ReadError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn decodes_ds18b20() {
+        let measurement = decode_ds18b20(&ds18b20(0x72, 0x01, 0x7f)).unwrap();
+        assert_eq!(measurement.celsius, 23.125);
+        assert_eq!(measurement.resolution, Some(12));
+
+        let measurement = decode_ds18b20(&ds18b20(0x5e, 0xff, 0x7f)).unwrap();
+        assert_eq!(measurement.celsius, -10.125);
+    }
+
+    #[test]
+    fn masks_undefined_bits_below_12_bits() {
+        // 0x0177 is 23.4375°C; lower resolutions leave the low bits undefined.
+        let decode = |config| decode_ds18b20(&ds18b20(0x77, 0x01, config)).unwrap();
+        assert_eq!(decode(0x1f).celsius, 23.0);
+        assert_eq!(decode(0x1f).resolution, Some(9));
+        assert_eq!(decode(0x3f).celsius, 23.25);
+        assert_eq!(decode(0x3f).resolution, Some(10));
+        assert_eq!(decode(0x5f).celsius, 23.375);
+        assert_eq!(decode(0x5f).resolution, Some(11));
+        assert_eq!(decode(0x7f).celsius, 23.4375);
+    }
+
+    #[test]
+    fn decodes_ds18s20_with_count_remain() {
+        // 0x002d is 22.5°C; TEMP_READ truncates it to 22°C.
+        let measurement = decode_ds18s20(&ds18s20(0x2d, 0x00, 0x0c)).unwrap();
+        assert_eq!(measurement.celsius, 22.0);
+        let measurement = decode_ds18s20(&ds18s20(0x2d, 0x00, 0x02)).unwrap();
+        assert_eq!(measurement.celsius, 22.625);
+        assert_eq!(measurement.resolution, None);
+    }
+
+    #[test]
+    fn decodes_negative_ds18s20() {
+        // 0xffce is -25°C, 0xffff is -0.5°C which TEMP_READ truncates to -1°C.
+        assert_eq!(
+            decode_ds18s20(&ds18s20(0xce, 0xff, 0x0a)).unwrap().celsius,
+            -24.875
+        );
+        assert_eq!(
+            decode_ds18s20(&ds18s20(0xff, 0xff, 0x0c)).unwrap().celsius,
+            -1.0
+        );
+    }
+
+    #[test]
+    fn falls_back_to_half_degrees_without_count_per_c() {
+        let scratchpad = Scratchpad([0x2d, 0x00, 0x4b, 0x46, 0xff, 0xff, 0x0c, 0x00, 0x00]);
+        assert_eq!(decode_ds18s20(&scratchpad).unwrap().celsius, 22.5);
+    }
+
+    #[test]
+    fn rejects_blank_scratchpads() {
+        for decode in [decode_ds18b20, decode_ds18s20] {
+            assert!(matches!(
+                decode(&Scratchpad([0x00; 9])),
+                Err(ReadError::Sentinel(Sentinel::Disconnected))
+            ));
+            assert!(matches!(
+                decode(&Scratchpad([0xff; 9])),
+                Err(ReadError::Sentinel(Sentinel::BusError))
+            ));
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use super::Slave;
+use std::{fs, io, time::Duration};
+
+/// Bit of the `features` attribute that polls the bus for the end of a
+/// conversion. A parasite-powered sensor cannot answer while converting, so
+/// the driver must wait the full conversion time instead.
+const FEATURE_POLL_COMPLETION: u8 = 0b10;
+
+impl Slave {
+    /// The configured conversion resolution in bits, or `None` if the
+    /// driver does not expose the `resolution` attribute.
+    pub fn resolution(&self) -> io::Result<Option<u8>> {
+        read_attribute(self, "resolution")
+    }
+
+    pub fn set_resolution(&self, bits: u8) -> io::Result<()> {
+        fs::write(self.path.join("resolution"), bits.to_string())
+    }
+
+    /// The conversion time the driver waits for, in milliseconds.
+    pub fn conv_time(&self) -> io::Result<Option<u64>> {
+        read_attribute(self, "conv_time")
+    }
+
+    pub fn set_conv_time(&self, conv_time: Duration) -> io::Result<()> {
+        fs::write(
+            self.path.join("conv_time"),
+            conv_time.as_millis().to_string(),
+        )
+    }
+
+    /// The TL and TH alarm thresholds in degrees Celsius.
+    pub fn alarms(&self) -> io::Result<Option<(i8, i8)>> {
+        match fs::read_to_string(self.path.join("alarms")) {
+            Ok(content) => {
+                let mut values = content.split_whitespace().map(str::parse);
+                match (values.next(), values.next()) {
+                    (Some(Ok(low)), Some(Ok(high))) => Ok(Some((low, high))),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected alarms value {:?}", content.trim()),
+                    )),
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_alarms(&self, low: i8, high: i8) -> io::Result<()> {
+        fs::write(self.path.join("alarms"), format!("{} {}", low, high))
+    }
+
+    /// Whether the sensor has its own power supply (`true`) or is parasite
+    /// powered from the data line (`false`).
+    pub fn external_power(&self) -> io::Result<Option<bool>> {
+        Ok(read_attribute::<u8>(self, "ext_power")?.map(|power| power == 1))
+    }
+
+    pub fn features(&self) -> io::Result<Option<u8>> {
+        read_attribute(self, "features")
+    }
+
+    pub fn set_features(&self, features: u8) -> io::Result<()> {
+        fs::write(self.path.join("features"), features.to_string())
+    }
+
+    /// Copies the scratchpad settings to the chip's EEPROM.
+    pub fn save_to_eeprom(&self) -> io::Result<()> {
+        fs::write(self.path.join("eeprom_cmd"), "save")
+    }
+}
+
+/// Reads a numeric sysfs attribute of the slave. A missing attribute, as on
+/// kernels before 5.10, is not an error.
+fn read_attribute<T: std::str::FromStr>(slave: &Slave, name: &str) -> io::Result<Option<T>> {
+    match fs::read_to_string(slave.path.join(name)) {
+        Ok(content) => content.trim().parse().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected {} value {:?}", name, content.trim()),
+            )
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Settings to apply to a device. `None` leaves a setting untouched.
+#[derive(Debug, Clone, Default)]
+pub struct TargetSettings {
+    pub resolution: Option<u8>,
+    pub conv_time: Option<Duration>,
+    /// TL and TH alarm thresholds in degrees Celsius.
+    pub alarms: Option<(i8, i8)>,
+    /// Save changed scratchpad settings to the EEPROM.
+    pub persist: bool,
+    /// For parasite-powered sensors, disable conversion polling and wait the
+    /// full datasheet conversion time, unless `conv_time` is set.
+    pub parasite_conversion: bool,
+}
+
+/// Datasheet maximum conversion time of a DS18B20 at the given resolution.
+fn datasheet_conv_time(resolution: u8) -> Duration {
+    Duration::from_micros(93_750 << resolution.saturating_sub(9).min(3))
+}
+
+/// Brings the device in line with the target settings. Only settings that
+/// differ are written, and the EEPROM is only written when `persist` is set
+/// and something changed, as it wears out after some 50k writes.
+pub fn reconcile(slave: &Slave, target: &TargetSettings) -> io::Result<()> {
+    let mut changed = false;
+
+    let mut effective = slave.resolution()?;
+    if let Some(bits) = target.resolution {
+        if effective.is_none() {
+            eprintln!("{} does not support setting the resolution", slave.id);
+        } else if effective != Some(bits) {
+            slave.set_resolution(bits)?;
+            println!("Set resolution of {} to {} bits", slave.id, bits);
+            effective = slave.resolution()?;
+            changed = true;
+        }
+    }
+
+    let mut conv_time = target.conv_time;
+    if target.parasite_conversion && slave.external_power()? == Some(false) {
+        if let Some(features) = slave.features()? {
+            if features & FEATURE_POLL_COMPLETION != 0 {
+                slave.set_features(features & !FEATURE_POLL_COMPLETION)?;
+                println!(
+                    "Disabled conversion polling for parasite-powered {}",
+                    slave.id
+                );
+            }
+        }
+        conv_time = conv_time.or(Some(datasheet_conv_time(effective.unwrap_or(12))));
+    }
+
+    if let Some(conv_time) = conv_time {
+        match slave.conv_time()? {
+            None => eprintln!("{} does not support setting the conversion time", slave.id),
+            Some(current) if u128::from(current) == conv_time.as_millis() => {}
+            Some(_) => {
+                slave.set_conv_time(conv_time)?;
+                println!(
+                    "Set conversion time of {} to {}ms",
+                    slave.id,
+                    conv_time.as_millis()
+                );
+            }
+        }
+    }
+
+    if let Some((low, high)) = target.alarms {
+        match slave.alarms()? {
+            None => eprintln!("{} does not support alarm thresholds", slave.id),
+            Some(current) if current == (low, high) => {}
+            Some(_) => {
+                slave.set_alarms(low, high)?;
+                println!("Set alarms of {} to {}°C/{}°C", slave.id, low, high);
+                changed = true;
+            }
+        }
+    }
+
+    if target.persist && changed {
+        slave.save_to_eeprom()?;
+        println!("Saved settings of {} to EEPROM", slave.id);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::Path, process};
+
+    /// A DS18B20 with the given sysfs attributes, in a directory of its own
+    /// under the system temp dir.
+    fn slave(name: &str, attributes: &[(&str, &str)]) -> Slave {
+        let path = std::env::temp_dir().join(format!("temperatures-{}-{}", process::id(), name));
+        fs::create_dir_all(&path).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(path.join(attribute), value).unwrap();
+        }
+        Slave {
+            id: "28-0316a2791aff".to_string(),
+            path,
+            bus: None,
+        }
+    }
+
+    fn attribute(path: &Path, name: &str) -> Option<String> {
+        fs::read_to_string(path.join(name))
+            .ok()
+            .map(|value| value.trim().to_string())
+    }
+
+    const DS18B20: &[(&str, &str)] = &[
+        ("resolution", "12\n"),
+        ("conv_time", "750\n"),
+        ("alarms", "-10 40\n"),
+        ("ext_power", "1\n"),
+        ("features", "0\n"),
+    ];
+
+    #[test]
+    fn leaves_matching_settings_alone() {
+        let slave = slave("unchanged", DS18B20);
+        let target = TargetSettings {
+            resolution: Some(12),
+            conv_time: Some(Duration::from_millis(750)),
+            alarms: Some((-10, 40)),
+            persist: true,
+            parasite_conversion: true,
+        };
+        reconcile(&slave, &target).unwrap();
+        let eeprom = attribute(&slave.path, "eeprom_cmd");
+        fs::remove_dir_all(&slave.path).unwrap();
+        assert_eq!(eeprom, None);
+    }
+
+    #[test]
+    fn writes_changed_settings() {
+        let slave = slave("changed", DS18B20);
+        let target = TargetSettings {
+            resolution: Some(10),
+            conv_time: Some(Duration::from_millis(200)),
+            alarms: Some((0, 30)),
+            persist: false,
+            parasite_conversion: false,
+        };
+        reconcile(&slave, &target).unwrap();
+        let values = ["resolution", "conv_time", "alarms", "eeprom_cmd"]
+            .map(|name| attribute(&slave.path, name));
+        fs::remove_dir_all(&slave.path).unwrap();
+        assert_eq!(
+            values,
+            [
+                Some("10".to_string()),
+                Some("200".to_string()),
+                Some("0 30".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn persists_only_changed_scratchpad_settings() {
+        let persist = |name: &str, target: TargetSettings| {
+            let slave = slave(name, DS18B20);
+            reconcile(&slave, &target).unwrap();
+            let eeprom = attribute(&slave.path, "eeprom_cmd");
+            fs::remove_dir_all(&slave.path).unwrap();
+            eeprom
+        };
+        let resolution = TargetSettings {
+            resolution: Some(9),
+            persist: true,
+            ..Default::default()
+        };
+        let alarms = TargetSettings {
+            alarms: Some((5, 6)),
+            persist: true,
+            ..Default::default()
+        };
+        // The conversion time is a driver setting, not part of the scratchpad.
+        let conv_time = TargetSettings {
+            conv_time: Some(Duration::from_millis(100)),
+            persist: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            persist("persist-resolution", resolution),
+            Some("save".into())
+        );
+        assert_eq!(persist("persist-alarms", alarms), Some("save".into()));
+        assert_eq!(persist("persist-conv-time", conv_time), None);
+    }
+
+    #[test]
+    fn waits_the_full_conversion_time_on_parasite_power() {
+        let slave = slave(
+            "parasite",
+            &[
+                ("resolution", "11\n"),
+                ("conv_time", "750\n"),
+                ("ext_power", "0\n"),
+                ("features", "3\n"),
+            ],
+        );
+        let target = TargetSettings {
+            parasite_conversion: true,
+            ..Default::default()
+        };
+        reconcile(&slave, &target).unwrap();
+        let values = ["features", "conv_time"].map(|name| attribute(&slave.path, name));
+        fs::remove_dir_all(&slave.path).unwrap();
+        assert_eq!(values, [Some("1".to_string()), Some("375".to_string())]);
+    }
+
+    #[test]
+    fn skips_settings_the_driver_does_not_support() {
+        let slave = slave("old-kernel", &[]);
+        let target = TargetSettings {
+            resolution: Some(10),
+            conv_time: Some(Duration::from_millis(200)),
+            alarms: Some((0, 30)),
+            persist: true,
+            parasite_conversion: true,
+        };
+        let result = reconcile(&slave, &target);
+        let written = fs::read_dir(&slave.path).unwrap().count();
+        fs::remove_dir_all(&slave.path).unwrap();
+        result.unwrap();
+        assert_eq!(written, 0);
+    }
+}